@@ -0,0 +1,123 @@
+//! Construction of async readers and writers, shared by the tokio and `futures-io` modules.
+//!
+//! The runtimes have traits of the same shape but different types, so the code is expanded in each module by a macro.
+
+/// Define `sniff_with_fallback()`, `new_reader()`, `read_header()` and `new_writer()` for `$reader` and `$writer`.
+///
+/// The calling module imports the traits, `AsyncReadExt`, `BufReader`, `BufWriter`, `Cursor`
+/// and codecs of `async-compression` for its runtime.
+/// `$reader` and `$writer` have `inner`, `format` and `path` fields.
+macro_rules! async_constructors {
+    ($reader:ident, $writer:ident) => {
+        async fn sniff_with_fallback<R>(
+            options: &ReadOptions,
+            mut r: R,
+            fallback: Format,
+        ) -> Result<$reader>
+        where
+            R: 'static + AsyncRead + Send + Unpin,
+        {
+            let mut header = [0u8; Format::SNIFF_LEN];
+            let n = read_header(&mut r, &mut header).await?;
+            let header = &header[..n];
+
+            let format = detect_format(header, fallback);
+            new_reader(options, Cursor::new(header.to_vec()).chain(r), format)
+        }
+
+        fn new_reader<R>(options: &ReadOptions, r: R, format: Format) -> Result<$reader>
+        where
+            R: 'static + AsyncRead + Send + Unpin,
+        {
+            let r = BufReader::new(SourceReader(r));
+            let multi_member = options.multi_member;
+
+            let inner: Pin<Box<dyn AsyncBufRead + Send>> = match format {
+                Format::Plain => Box::pin(r),
+                Format::Gzip | Format::Bgzf => {
+                    let mut d = GzipDecoder::new(r);
+                    d.multiple_members(multi_member);
+                    Box::pin(BufReader::new(d))
+                }
+                Format::Lz4 => {
+                    let mut d = Lz4Decoder::new(r);
+                    d.multiple_members(multi_member);
+                    Box::pin(BufReader::new(d))
+                }
+                Format::Zstd => {
+                    let mut d = ZstdDecoder::new(r);
+                    d.multiple_members(multi_member);
+                    Box::pin(BufReader::new(d))
+                }
+                Format::Xz => {
+                    let mut d = XzDecoder::new(r);
+                    d.multiple_members(multi_member);
+                    Box::pin(BufReader::new(d))
+                }
+                Format::Lzma => Box::pin(BufReader::new(LzmaDecoder::new(r))),
+                Format::Bzip2 => {
+                    let mut d = BzDecoder::new(r);
+                    d.multiple_members(multi_member);
+                    Box::pin(BufReader::new(d))
+                }
+            };
+
+            Ok($reader {
+                inner,
+                format,
+                path: None,
+            })
+        }
+
+        async fn read_header<R: AsyncRead + Unpin>(r: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+            let mut n = 0;
+            while n < buf.len() {
+                match r.read(&mut buf[n..]).await {
+                    Ok(0) => break,
+                    Ok(m) => n += m,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(n)
+        }
+
+        fn new_writer<W>(
+            options: &WriteOptions,
+            w: W,
+            format: Format,
+            level: Level,
+        ) -> Result<$writer>
+        where
+            W: 'static + AsyncWrite + Send + Unpin,
+        {
+            let level = options.level_overrides.resolve(format, level);
+            let level = level.into_async_level(format)?;
+
+            let inner: Pin<Box<dyn AsyncWrite + Send>> = match format {
+                Format::Plain => Box::pin(BufWriter::new(w)),
+                Format::Gzip => Box::pin(GzipEncoder::with_quality(w, level)),
+                Format::Lz4 => Box::pin(Lz4Encoder::with_quality(w, level)),
+                Format::Zstd => {
+                    let params = [async_compression::zstd::CParameter::checksum_flag(true)];
+                    Box::pin(ZstdEncoder::with_quality_and_params(w, level, &params))
+                }
+                Format::Xz => Box::pin(XzEncoder::with_quality(w, level)),
+                Format::Lzma => Box::pin(LzmaEncoder::with_quality(w, level)),
+                Format::Bzip2 => Box::pin(BzEncoder::with_quality(w, level)),
+                Format::Bgzf => {
+                    return Err(
+                        Error::invalid_input("BGZF is not supported by async writers")
+                            .with_format(format),
+                    )
+                }
+            };
+
+            Ok($writer {
+                inner,
+                format,
+                path: None,
+            })
+        }
+    };
+}
@@ -0,0 +1,102 @@
+//! Atomic file creation by writing to a temporary sibling and renaming it into place.
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Temporary file to be renamed to the destination on commit.
+///
+/// The temporary file is removed when dropped without commit.
+#[derive(Debug)]
+pub(crate) struct AtomicFile {
+    /// Another handle of the temporary file, used for `fsync`.
+    file: File,
+    temp: PathBuf,
+    dest: PathBuf,
+    sync_dir: bool,
+    committed: bool,
+}
+
+impl AtomicFile {
+    /// Create a temporary file next to `dest`.
+    ///
+    /// Returns the file to write and the handle to commit or discard it.
+    pub(crate) fn create(dest: &Path, sync_dir: bool) -> Result<(File, AtomicFile)> {
+        let name = dest
+            .file_name()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "destination has no file name"))?;
+
+        loop {
+            let mut temp_name = OsString::from(".");
+            temp_name.push(name);
+            temp_name.push(format!(
+                ".{}-{}.tmp",
+                process::id(),
+                TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            let temp = dest.with_file_name(temp_name);
+
+            match OpenOptions::new().write(true).create_new(true).open(&temp) {
+                Ok(f) => {
+                    let file = f.try_clone()?;
+                    let atomic = AtomicFile {
+                        file,
+                        temp,
+                        dest: dest.to_path_buf(),
+                        sync_dir,
+                        committed: false,
+                    };
+                    return Ok((f, atomic));
+                }
+                // Left by another writer. Try next name.
+                Err(ref e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Sync the temporary file and rename it to the destination.
+    ///
+    /// All data must be flushed to the file before commit.
+    /// Other handles of the file should be closed before the temporary file is discarded,
+    /// because an open file can't be removed on some platforms.
+    pub(crate) fn commit(mut self) -> Result<()> {
+        self.file.sync_all()?;
+        fs::rename(&self.temp, &self.dest)?;
+        self.committed = true;
+
+        if self.sync_dir {
+            sync_parent(&self.dest)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.temp);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if dir.as_os_str().is_empty() => Path::new("."),
+        Some(dir) => dir,
+        None => return Ok(()),
+    };
+    File::open(dir)?.sync_all()
+}
+
+/// Directories can't be opened as `File` on this platform.
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> Result<()> {
+    Ok(())
+}
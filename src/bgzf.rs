@@ -0,0 +1,831 @@
+//! BGZF, the blocked gzip format of `bgzip`.
+//!
+//! BGZF file is a series of gzip members up to 64 KiB, each having its size in the `BC` extra subfield.
+//! Members can be found without decoding, so they are decoded in parallel.
+
+use std::cell::RefCell;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use flate2::read::MultiGzDecoder;
+use flate2::{Compress, Compression, Crc, Decompress, FlushCompress, FlushDecompress, Status};
+
+use crate::atomic::AtomicFile;
+use crate::error::mark_io;
+use crate::pool::OrderedPool;
+use crate::seek::Chunk;
+use crate::{Error, GzipHeader};
+
+// Header flags.
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+/// Size of CRC32 and ISIZE.
+const TRAILER_LEN: usize = 8;
+
+/// Max size of uncompressed data in a block.
+const MAX_BLOCK_DATA: usize = 64 * 1024;
+
+/// Max size of a block.
+const MAX_BLOCK_SIZE: usize = 64 * 1024;
+
+/// Size of uncompressed data in a block on write, same as `bgzip`.
+///
+/// Even incompressible data fits in a block as stored deflate blocks.
+const BLOCK_DATA_LEN: usize = 0xff00;
+
+/// Size of header written by `BgzfWriter`.
+const HEADER_LEN: usize = 18;
+
+/// Empty block marks end of file.
+const EOF_BLOCK: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00, 0x42, 0x43, 0x02, 0x00,
+    0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+
+/// Stream with peeked bytes restored.
+pub(crate) type Peeked<R> = io::Chain<Cursor<Vec<u8>>, R>;
+
+/// Gzip member header.
+struct Header {
+    gzip: GzipHeader,
+    /// Total size of the member from `BC` subfield, if BGZF.
+    block_size: Option<usize>,
+    /// Size of the header.
+    len: usize,
+}
+
+/// Read leading member header, and find whether the stream is BGZF.
+///
+/// Returns the header if BGZF, and the stream with the read bytes restored.
+pub(crate) fn peek_header<R: Read>(mut r: R) -> io::Result<(Option<GzipHeader>, Peeked<R>)> {
+    let mut raw = Vec::new();
+    let header = match read_header(&mut r, &mut raw) {
+        Ok(Some(header)) if header.block_size.is_some() => Some(header.gzip),
+        // Invalid data is reported by the normal decoder.
+        Ok(_) => None,
+        Err(ref e)
+            if e.kind() == io::ErrorKind::InvalidData
+                || e.kind() == io::ErrorKind::UnexpectedEof =>
+        {
+            None
+        }
+        Err(e) => return Err(e),
+    };
+    Ok((header, Cursor::new(raw).chain(r)))
+}
+
+/// Read a member header, appending the read bytes to `raw`.
+///
+/// Returns `None` at end of stream.
+fn read_header<R: Read>(r: &mut R, raw: &mut Vec<u8>) -> io::Result<Option<Header>> {
+    let start = raw.len();
+
+    if read_some(r, raw, 1)? == 0 {
+        return Ok(None);
+    }
+    read_exact(r, raw, 9)?;
+    let fixed = &raw[start..];
+    if fixed[0..3] != [0x1f, 0x8b, 8] {
+        return Err(invalid_data("invalid gzip header"));
+    }
+    let flags = fixed[3];
+
+    let mut gzip = GzipHeader::new();
+    gzip.mtime = u32::from_le_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]);
+    gzip.operating_system = fixed[9];
+
+    let mut block_size = None;
+    if flags & FEXTRA != 0 {
+        let xlen = read_exact(r, raw, 2)?;
+        let xlen = usize::from(u16::from_le_bytes([xlen[0], xlen[1]]));
+        let extra = read_exact(r, raw, xlen)?.to_vec();
+        block_size = find_block_size(&extra);
+        gzip.extra = Some(extra);
+    }
+    if flags & FNAME != 0 {
+        gzip.filename = Some(read_zero_terminated(r, raw)?);
+    }
+    if flags & FCOMMENT != 0 {
+        gzip.comment = Some(read_zero_terminated(r, raw)?);
+    }
+    if flags & FHCRC != 0 {
+        read_exact(r, raw, 2)?;
+    }
+
+    Ok(Some(Header {
+        gzip,
+        block_size,
+        len: raw.len() - start,
+    }))
+}
+
+/// Find `BSIZE` in `BC` subfield, and return the total block size.
+fn find_block_size(mut extra: &[u8]) -> Option<usize> {
+    while extra.len() >= 4 {
+        let len = usize::from(u16::from_le_bytes([extra[2], extra[3]]));
+        let data = extra.get(4..4 + len)?;
+        if extra[0..2] == *b"BC" && len == 2 {
+            return Some(usize::from(u16::from_le_bytes([data[0], data[1]])) + 1);
+        }
+        extra = &extra[4 + len..];
+    }
+    None
+}
+
+fn read_some<R: Read>(r: &mut R, raw: &mut Vec<u8>, len: usize) -> io::Result<usize> {
+    let start = raw.len();
+    r.by_ref().take(len as u64).read_to_end(raw)?;
+    Ok(raw.len() - start)
+}
+
+fn read_exact<'a, R: Read>(r: &mut R, raw: &'a mut Vec<u8>, len: usize) -> io::Result<&'a [u8]> {
+    let start = raw.len();
+    if read_some(r, raw, len)? < len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "gzip member ends in the middle",
+        ));
+    }
+    Ok(&raw[start..])
+}
+
+fn read_zero_terminated<R: Read>(r: &mut R, raw: &mut Vec<u8>) -> io::Result<Vec<u8>> {
+    let start = raw.len();
+    loop {
+        if read_exact(r, raw, 1)? == [0] {
+            return Ok(raw[start..raw.len() - 1].to_vec());
+        }
+    }
+}
+
+/// BGZF decoder decodes blocks on worker threads, reading ahead.
+///
+/// A gzip member without `BC` subfield may follow, e.g. concatenated with `cat`.
+/// It and the rest of the stream are decoded by `MultiGzDecoder` in the calling thread.
+pub(crate) struct BgzfReader<R> {
+    state: State<R>,
+    pool: OrderedPool<io::Result<Vec<u8>>>,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+/// Where the data after submitted blocks comes from.
+enum State<R> {
+    /// Reading blocks.
+    Blocks(R),
+    /// Plain gzip members, with the read header restored.
+    Gzip(Box<io::BufReader<MultiGzDecoder<Peeked<R>>>>),
+    /// Reading ahead failed. Returned after the submitted blocks.
+    Error(io::Error),
+    /// All blocks are read.
+    End,
+}
+
+/// Member read from the stream.
+enum Member {
+    /// BGZF block, whose header is removed.
+    Block(Vec<u8>),
+    /// Gzip member without block size, with read bytes of its header.
+    Plain(Vec<u8>),
+    End,
+}
+
+impl<R: Read> BgzfReader<R> {
+    pub(crate) fn new(inner: R, threads: usize) -> BgzfReader<R> {
+        BgzfReader {
+            state: State::Blocks(inner),
+            pool: OrderedPool::new(threads),
+            buf: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    /// Read blocks and submit them until the pool is full.
+    ///
+    /// Errors are kept until the blocks before them are consumed.
+    fn read_ahead(&mut self) {
+        while !self.pool.is_full() {
+            let inner = match self.state {
+                State::Blocks(ref mut inner) => inner,
+                _ => return,
+            };
+            let res = match read_block(inner) {
+                Ok(Member::Block(block)) => self.pool.submit(move || decode(block)),
+                Ok(Member::Plain(raw)) => {
+                    if let State::Blocks(inner) = mem::replace(&mut self.state, State::End) {
+                        let d = MultiGzDecoder::new(Cursor::new(raw).chain(inner));
+                        self.state = State::Gzip(Box::new(io::BufReader::new(d)));
+                    }
+                    Ok(())
+                }
+                Ok(Member::End) => {
+                    self.state = State::End;
+                    Ok(())
+                }
+                Err(e) => Err(e),
+            };
+            if let Err(e) = res {
+                self.state = State::Error(e);
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for BgzfReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.fill_buf()?.read(buf)?;
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for BgzfReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        // Loop to skip empty blocks, like the EOF marker.
+        while self.pos == self.buf.len() {
+            self.read_ahead();
+            match self.pool.next() {
+                Some(res) => {
+                    self.buf = res??;
+                    self.pos = 0;
+                }
+                None => break,
+            }
+        }
+        if self.pos < self.buf.len() {
+            return Ok(&self.buf[self.pos..]);
+        }
+
+        // All submitted blocks are consumed.
+        if let State::Error(_) = self.state {
+            if let State::Error(e) = mem::replace(&mut self.state, State::End) {
+                return Err(e);
+            }
+        }
+        match self.state {
+            State::Gzip(ref mut r) => r.fill_buf(),
+            _ => Ok(&[]),
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if self.pos < self.buf.len() {
+            self.pos = (self.pos + amt).min(self.buf.len());
+        } else if let State::Gzip(ref mut r) = self.state {
+            r.consume(amt);
+        }
+    }
+}
+
+/// Read a member, and the whole data if it is a BGZF block.
+fn read_block<R: Read>(r: &mut R) -> io::Result<Member> {
+    let mut raw = Vec::new();
+    let header = match read_header(r, &mut raw)? {
+        Some(header) => header,
+        None => return Ok(Member::End),
+    };
+    let block_size = match header.block_size {
+        Some(block_size) => block_size,
+        None => return Ok(Member::Plain(raw)),
+    };
+    if block_size < header.len + TRAILER_LEN {
+        return Err(invalid_data("invalid BGZF block size"));
+    }
+
+    raw.clear();
+    read_exact(r, &mut raw, block_size - header.len)?;
+    Ok(Member::Block(raw))
+}
+
+/// Decode deflate data and check the trailer.
+fn decode(block: Vec<u8>) -> io::Result<Vec<u8>> {
+    let (data, trailer) = block.split_at(block.len() - TRAILER_LEN);
+    let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+
+    let mut d = Decompress::new(false);
+    // Don't trust size for allocation.
+    let mut out = Vec::with_capacity((size as usize).min(MAX_BLOCK_DATA));
+    loop {
+        let pos = d.total_in() as usize;
+        let before = out.len();
+        let status = d.decompress_vec(&data[pos..], &mut out, FlushDecompress::Finish)?;
+        match status {
+            Status::StreamEnd => break,
+            _ if out.len() == out.capacity() => out.reserve(MAX_BLOCK_DATA),
+            // The member is complete, so this is not truncation of the file.
+            _ if d.total_in() as usize == pos && out.len() == before => {
+                return Err(invalid_data(
+                    "deflate stream ends in the middle of BGZF block",
+                ))
+            }
+            _ => {}
+        }
+    }
+
+    let mut actual = Crc::new();
+    actual.update(&out);
+    if actual.sum() != crc || actual.amount() != size {
+        return Err(invalid_data(
+            "corrupt gzip stream does not have a matching checksum",
+        ));
+    }
+    Ok(out)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Find blocks by their headers, without decoding.
+///
+/// Each block is a chunk.
+pub(crate) fn scan_blocks<R: Read + Seek>(r: &mut R) -> io::Result<Vec<Chunk>> {
+    let mut chunks = Vec::new();
+    let mut compressed = r.seek(SeekFrom::Start(0))?;
+    let mut uncompressed = 0;
+
+    let mut raw = Vec::new();
+    loop {
+        raw.clear();
+        let header = match read_header(r, &mut raw)? {
+            Some(header) => header,
+            None => break,
+        };
+        // Valid, but members of plain gzip are found only by decoding.
+        let block_size = header.block_size.ok_or_else(|| -> io::Error {
+            Error::invalid_input("gzip member without BGZF block size is not seekable").into()
+        })?;
+        if block_size < header.len + TRAILER_LEN {
+            return Err(invalid_data("invalid BGZF block size"));
+        }
+
+        // ISIZE at the end of the block.
+        r.seek(SeekFrom::Start(compressed + block_size as u64 - 4))?;
+        raw.clear();
+        let size = read_exact(r, &mut raw, 4)?;
+        let size = u32::from_le_bytes([size[0], size[1], size[2], size[3]]);
+
+        chunks.push(Chunk {
+            compressed,
+            uncompressed,
+        });
+        compressed += block_size as u64;
+        uncompressed += u64::from(size);
+    }
+
+    chunks.push(Chunk {
+        compressed,
+        uncompressed,
+    });
+    Ok(chunks)
+}
+
+/// Read `.gzi` index of the file, and make chunks from it.
+///
+/// Returns `None` if the index doesn't exist.
+/// The index doesn't have the size of the last block, so it is decoded.
+pub(crate) fn read_index<R: Read + Seek>(r: &mut R, gzi: &Path) -> io::Result<Option<Vec<Chunk>>> {
+    let mut f = match File::open(gzi) {
+        Ok(f) => io::BufReader::new(f),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(mark_io(e)),
+    };
+    let mut entry = [0u8; 16];
+
+    f.read_exact(&mut entry[..8]).map_err(mark_io)?;
+    let n = u64::from_le_bytes(entry[..8].try_into().expect("8 bytes"));
+    let mut chunks = vec![Chunk {
+        compressed: 0,
+        uncompressed: 0,
+    }];
+    for _ in 0..n {
+        f.read_exact(&mut entry).map_err(mark_io)?;
+        chunks.push(Chunk {
+            compressed: u64::from_le_bytes(entry[..8].try_into().expect("8 bytes")),
+            uncompressed: u64::from_le_bytes(entry[8..].try_into().expect("8 bytes")),
+        });
+    }
+
+    let last = chunks[chunks.len() - 1];
+    let end = r.seek(SeekFrom::End(0))?;
+    let ordered = chunks
+        .windows(2)
+        .all(|w| w[0].compressed < w[1].compressed && w[0].uncompressed <= w[1].uncompressed);
+    if !ordered || last.compressed >= end {
+        return Err(invalid_data("BGZF index doesn't match the file"));
+    }
+
+    r.seek(SeekFrom::Start(last.compressed))?;
+    let mut raw = Vec::new();
+    r.read_to_end(&mut raw)?;
+    let data = decode_blocks(&raw)?;
+
+    chunks.push(Chunk {
+        compressed: end,
+        uncompressed: last.uncompressed + data.len() as u64,
+    });
+    Ok(Some(chunks))
+}
+
+/// Path of `.gzi` index of `path`.
+pub(crate) fn gzi_path(path: &Path) -> PathBuf {
+    let mut gzi = path.as_os_str().to_owned();
+    gzi.push(".gzi");
+    PathBuf::from(gzi)
+}
+
+/// Decode consecutive blocks.
+pub(crate) fn decode_blocks(mut raw: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    loop {
+        match read_block(&mut raw)? {
+            Member::Block(block) => out.extend_from_slice(&decode(block)?),
+            Member::Plain(_) => return Err(invalid_data("gzip member without BGZF block size")),
+            Member::End => return Ok(out),
+        }
+    }
+}
+
+/// BGZF encoder compresses blocks on worker threads.
+pub(crate) struct BgzfWriter<W: Write> {
+    inner: W,
+    level: Compression,
+    buf: Vec<u8>,
+    pool: OrderedPool<io::Result<Block>>,
+    index: Option<GziIndex>,
+    /// Offsets of the next block.
+    compressed: u64,
+    uncompressed: u64,
+}
+
+struct Block {
+    data: Vec<u8>,
+    uncompressed: usize,
+}
+
+impl<W: Write> BgzfWriter<W> {
+    pub(crate) fn new(
+        inner: W,
+        level: Compression,
+        threads: usize,
+        index: Option<GziIndex>,
+    ) -> BgzfWriter<W> {
+        BgzfWriter {
+            inner,
+            level,
+            buf: Vec::with_capacity(BLOCK_DATA_LEN),
+            pool: OrderedPool::new(threads),
+            index,
+            compressed: 0,
+            uncompressed: 0,
+        }
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Compress all buffered data, and write the EOF marker block.
+    pub(crate) fn try_finish(&mut self) -> io::Result<()> {
+        self.write_all_blocks()?;
+        self.inner.write_all(&EOF_BLOCK)
+    }
+
+    fn submit(&mut self) -> io::Result<()> {
+        while self.pool.is_full() {
+            self.write_next()?;
+        }
+
+        let data = mem::replace(&mut self.buf, Vec::with_capacity(BLOCK_DATA_LEN));
+        let level = self.level;
+        self.pool.submit(move || compress(&data, level))
+    }
+
+    fn write_next(&mut self) -> io::Result<()> {
+        if let Some(res) = self.pool.next() {
+            let block = res??;
+            self.inner.write_all(&block.data)?;
+
+            // The first block at offset 0 is implicit in the index.
+            if let Some(ref index) = self.index {
+                if self.compressed > 0 {
+                    index.push(self.compressed, self.uncompressed);
+                }
+            }
+            self.compressed += block.data.len() as u64;
+            self.uncompressed += block.uncompressed as u64;
+        }
+        Ok(())
+    }
+
+    fn write_all_blocks(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            self.submit()?;
+        }
+        while !self.pool.is_empty() {
+            self.write_next()?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for BgzfWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(BLOCK_DATA_LEN - self.buf.len());
+        self.buf.extend_from_slice(&buf[..n]);
+
+        if self.buf.len() == BLOCK_DATA_LEN {
+            self.submit()?;
+        }
+        Ok(n)
+    }
+
+    /// Flush ends current block.
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_all_blocks()?;
+        self.inner.flush()
+    }
+}
+
+/// Compress data into a block with header and trailer.
+fn compress(data: &[u8], level: Compression) -> io::Result<Block> {
+    let mut deflated = deflate(data, level)?;
+    if HEADER_LEN + deflated.len() + TRAILER_LEN > MAX_BLOCK_SIZE {
+        deflated = deflate(data, Compression::none())?;
+    }
+    let block_size = HEADER_LEN + deflated.len() + TRAILER_LEN;
+
+    let mut crc = Crc::new();
+    crc.update(data);
+
+    let mut block = Vec::with_capacity(block_size);
+    block.extend_from_slice(&[
+        0x1f, 0x8b, 8, FEXTRA, 0, 0, 0, 0, 0, 0xff, 6, 0, b'B', b'C', 2, 0,
+    ]);
+    block.extend_from_slice(&((block_size - 1) as u16).to_le_bytes());
+    block.extend_from_slice(&deflated);
+    block.extend_from_slice(&crc.sum().to_le_bytes());
+    block.extend_from_slice(&crc.amount().to_le_bytes());
+
+    Ok(Block {
+        data: block,
+        uncompressed: data.len(),
+    })
+}
+
+fn deflate(data: &[u8], level: Compression) -> io::Result<Vec<u8>> {
+    let mut c = Compress::new(level, false);
+    let mut out = Vec::with_capacity(data.len() + 64);
+
+    loop {
+        let pos = c.total_in() as usize;
+        let status = c.compress_vec(&data[pos..], &mut out, FlushCompress::Finish)?;
+        if status == Status::StreamEnd {
+            return Ok(out);
+        }
+        if out.len() == out.capacity() {
+            out.reserve(out.capacity());
+        }
+    }
+}
+
+/// Entries of `.gzi` index, shared with the writer.
+///
+/// Each entry is compressed and uncompressed offsets of a block start.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct GziIndex(Rc<RefCell<Vec<(u64, u64)>>>);
+
+impl GziIndex {
+    fn push(&self, compressed: u64, uncompressed: u64) {
+        self.0.borrow_mut().push((compressed, uncompressed));
+    }
+
+    fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let entries = self.0.borrow();
+        w.write_all(&(entries.len() as u64).to_le_bytes())?;
+        for &(compressed, uncompressed) in entries.iter() {
+            w.write_all(&compressed.to_le_bytes())?;
+            w.write_all(&uncompressed.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// `.gzi` index file written on finalize.
+pub(crate) struct GziOutput {
+    pub(crate) index: GziIndex,
+    path: PathBuf,
+    atomic: bool,
+    sync_dir: bool,
+}
+
+impl GziOutput {
+    /// Index of `path`, written to `path` with `.gzi` appended.
+    pub(crate) fn new(path: &Path, atomic: bool, sync_dir: bool) -> GziOutput {
+        GziOutput {
+            index: GziIndex::default(),
+            path: gzi_path(path),
+            atomic,
+            sync_dir,
+        }
+    }
+
+    pub(crate) fn write(self) -> io::Result<()> {
+        if self.atomic {
+            let (f, atomic) = AtomicFile::create(&self.path, self.sync_dir)?;
+            let mut w = BufWriter::new(f);
+            self.index.write_to(&mut w)?;
+            w.flush()?;
+            drop(w);
+            atomic.commit()
+        } else {
+            let mut w = BufWriter::new(File::create(&self.path)?);
+            self.index.write_to(&mut w)?;
+            w.flush()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read, Write};
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    use super::{
+        decode_blocks, scan_blocks, BgzfReader, BgzfWriter, GziIndex, BLOCK_DATA_LEN, EOF_BLOCK,
+        MAX_BLOCK_SIZE,
+    };
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| (i * 7 % 251) as u8 ^ (i / 1000) as u8)
+            .collect()
+    }
+
+    fn bgzf(data: &[u8], threads: usize) -> Vec<u8> {
+        let mut w = BgzfWriter::new(Vec::new(), Compression::default(), threads, None);
+        w.write_all(data).unwrap();
+        w.try_finish().unwrap();
+        w.get_mut().clone()
+    }
+
+    /// Incompressible data.
+    fn random(len: usize) -> Vec<u8> {
+        let mut x = 0x2545_f491_4f6c_dd1d_u64;
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect()
+    }
+
+    /// Write `data` by `chunk` bytes with flush between writes, and return the file and its `.gzi` index.
+    fn bgzf_with_index(data: &[u8], threads: usize, chunk: usize) -> (Vec<u8>, Vec<u8>) {
+        let index = GziIndex::default();
+        let mut w = BgzfWriter::new(
+            Vec::new(),
+            Compression::default(),
+            threads,
+            Some(index.clone()),
+        );
+        for c in data.chunks(chunk) {
+            w.write_all(c).unwrap();
+            w.flush().unwrap();
+        }
+        w.try_finish().unwrap();
+
+        let mut gzi = Vec::new();
+        index.write_to(&mut gzi).unwrap();
+        (w.get_mut().clone(), gzi)
+    }
+
+    fn u64_at(buf: &[u8], pos: usize) -> u64 {
+        let mut b = [0u8; 8];
+        b.copy_from_slice(&buf[pos..pos + 8]);
+        u64::from_le_bytes(b)
+    }
+
+    /// Read until error, returning the data read and the error.
+    fn read_until_error<R: Read>(mut r: R) -> (Vec<u8>, Option<std::io::Error>) {
+        let mut out = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            match r.read(&mut buf) {
+                Ok(0) => return (out, None),
+                Ok(n) => out.extend_from_slice(&buf[..n]),
+                Err(e) => return (out, Some(e)),
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_after_decoded_blocks() {
+        let data = sample(1_000_000);
+        let file = bgzf(&data, 1);
+        let cut = file.len() / 2;
+
+        // Data of blocks ending before the cut.
+        let chunks = scan_blocks(&mut Cursor::new(&file)).unwrap();
+        let complete = chunks
+            .iter()
+            .take_while(|c| c.compressed <= cut as u64)
+            .last()
+            .unwrap()
+            .uncompressed as usize;
+
+        let r = BgzfReader::new(&file[..cut], 4);
+        let (out, err) = read_until_error(r);
+        assert_eq!(out, &data[..complete]);
+        assert_eq!(err.unwrap().kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn plain_gzip_after_blocks() {
+        let first = sample(300_000);
+        let second = sample(200_000);
+        let mut file = bgzf(&first, 1);
+        let mut e = GzEncoder::new(&mut file, Compression::default());
+        e.write_all(&second).unwrap();
+        e.finish().unwrap();
+        file.extend_from_slice(&bgzf(&first, 1));
+
+        let mut out = Vec::new();
+        BgzfReader::new(&file[..], 4).read_to_end(&mut out).unwrap();
+        assert_eq!(out, [&first[..], &second[..], &first[..]].concat());
+    }
+
+    #[test]
+    fn block_layout() {
+        let data = [sample(500_000), random(300_000)].concat();
+        for &threads in &[1, 4] {
+            let file = bgzf(&data, threads);
+            assert!(file.ends_with(&EOF_BLOCK));
+            assert_eq!(decode_blocks(&file).unwrap(), data);
+
+            let chunks = scan_blocks(&mut Cursor::new(&file)).unwrap();
+            for w in chunks.windows(2) {
+                let (start, end) = (w[0], w[1]);
+                let block = &file[start.compressed as usize..end.compressed as usize];
+                assert_eq!(&block[..4], &[0x1f, 0x8b, 8, 4]);
+                assert_eq!(&block[12..16], b"BC\x02\x00");
+                assert!(block.len() <= MAX_BLOCK_SIZE);
+                assert!(end.uncompressed - start.uncompressed <= BLOCK_DATA_LEN as u64);
+            }
+        }
+    }
+
+    #[test]
+    fn incompressible_blocks_are_stored() {
+        let data = random(5 * BLOCK_DATA_LEN);
+        let file = bgzf(&data, 2);
+        let chunks = scan_blocks(&mut Cursor::new(&file)).unwrap();
+
+        // Full blocks, EOF block and the end.
+        assert_eq!(chunks.len(), 5 + 2);
+        for w in chunks[..5].windows(2) {
+            assert_eq!(w[1].uncompressed - w[0].uncompressed, BLOCK_DATA_LEN as u64);
+            assert!(w[1].compressed - w[0].compressed <= MAX_BLOCK_SIZE as u64);
+        }
+        assert_eq!(decode_blocks(&file).unwrap(), data);
+    }
+
+    #[test]
+    fn empty_input() {
+        let file = bgzf(&[], 2);
+        assert_eq!(file, EOF_BLOCK);
+        assert!(decode_blocks(&file).unwrap().is_empty());
+    }
+
+    #[test]
+    fn index_matches_blocks() {
+        let data = sample(1_000_000);
+        for &threads in &[1, 4] {
+            let (file, gzi) = bgzf_with_index(&data, threads, 100_000);
+            assert_eq!(decode_blocks(&file).unwrap(), data);
+
+            // The first block at offset 0 and the EOF block are not in the index.
+            let chunks = scan_blocks(&mut Cursor::new(&file)).unwrap();
+            let blocks = &chunks[1..chunks.len() - 2];
+
+            assert_eq!(u64_at(&gzi, 0), blocks.len() as u64);
+            assert_eq!(gzi.len(), 8 + 16 * blocks.len());
+            for (i, block) in blocks.iter().enumerate() {
+                assert_eq!(u64_at(&gzi, 8 + 16 * i), block.compressed);
+                assert_eq!(u64_at(&gzi, 16 + 16 * i), block.uncompressed);
+            }
+        }
+    }
+}
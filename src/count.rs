@@ -0,0 +1,132 @@
+//! Byte counting of compressed streams.
+
+use std::cell::Cell;
+use std::io::{self, Read, Result, Write};
+use std::rc::Rc;
+
+use crate::error::{Error, ErrorKind};
+
+/// Shared counter of bytes passed through a counting wrapper.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Counter(Rc<Cell<u64>>);
+
+impl Counter {
+    pub(crate) fn get(&self) -> u64 {
+        self.0.get()
+    }
+
+    fn add(&self, n: usize) {
+        self.0.set(self.0.get() + n as u64);
+    }
+}
+
+/// `Write` wrapper counts written bytes.
+pub(crate) struct CountWriter<W> {
+    inner: W,
+    counter: Counter,
+}
+
+impl<W: Write> CountWriter<W> {
+    pub(crate) fn new(inner: W, counter: Counter) -> CountWriter<W> {
+        CountWriter { inner, counter }
+    }
+}
+
+impl<W: Write> Write for CountWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.counter.add(n);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// `Read` wrapper counts read bytes.
+pub(crate) struct CountReader<R> {
+    inner: R,
+    counter: Counter,
+}
+
+impl<R: Read> CountReader<R> {
+    pub(crate) fn new(inner: R, counter: Counter) -> CountReader<R> {
+        CountReader { inner, counter }
+    }
+}
+
+impl<R: Read> Read for CountReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.counter.add(n);
+        Ok(n)
+    }
+}
+
+/// Limits of decompressed bytes.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Limit {
+    pub(crate) max_output: Option<u64>,
+    pub(crate) max_ratio: Option<f64>,
+}
+
+/// Counts decompressed bytes and checks them against `Limit`.
+pub(crate) struct LimitCounter {
+    limit: Limit,
+    bytes_in: Counter,
+    bytes_out: u64,
+}
+
+impl LimitCounter {
+    pub(crate) fn new(limit: Limit, bytes_in: Counter) -> LimitCounter {
+        LimitCounter {
+            limit,
+            bytes_in,
+            bytes_out: 0,
+        }
+    }
+
+    /// Check that `n` more decompressed bytes are within the limits.
+    pub(crate) fn check(&self, n: usize) -> crate::Result<()> {
+        let bytes_out = self.bytes_out + n as u64;
+
+        if let Some(max) = self.limit.max_output {
+            if bytes_out > max {
+                return Err(limit_exceeded(format!(
+                    "decompressed size exceeds {} bytes",
+                    max
+                )));
+            }
+        }
+
+        if let Some(max) = self.limit.max_ratio {
+            // Compressed bytes are read ahead of decompressed bytes, so this never underestimates.
+            let bytes_in = self.bytes_in.get();
+            if bytes_out as f64 > bytes_in as f64 * max {
+                return Err(limit_exceeded(format!(
+                    "{} bytes decompressed from {} bytes exceed ratio {}",
+                    bytes_out, bytes_in, max
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn add(&mut self, n: usize) {
+        self.bytes_out += n as u64;
+    }
+
+    pub(crate) fn bytes_in(&self) -> u64 {
+        self.bytes_in.get()
+    }
+
+    pub(crate) fn bytes_out(&self) -> u64 {
+        self.bytes_out
+    }
+}
+
+fn limit_exceeded(message: String) -> Error {
+    Error::new(ErrorKind::LimitExceeded).with_source(io::Error::other(message))
+}
@@ -0,0 +1,246 @@
+//! Error type with path and format context.
+
+use std::error;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::result;
+
+use crate::{Format, Level};
+
+/// Result type of this crate.
+pub type Result<T> = result::Result<T, Error>;
+
+/// The error type of this crate.
+///
+/// Errors have the [`path`](#method.path) and the [`format`](#method.format) of the file, if known.
+///
+/// This error converts into [`std::io::Error`](https://doc.rust-lang.org/std/io/struct.Error.html),
+/// and [`Read`](https://doc.rust-lang.org/std/io/trait.Read.html) and [`Write`](https://doc.rust-lang.org/std/io/trait.Write.html)
+/// implementations return this error inside `std::io::Error`.
+/// [`Error::from_io()`](#method.from_io) recovers it.
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    path: Option<PathBuf>,
+    format: Option<Format>,
+    source: Option<io::Error>,
+}
+
+/// Kind of [`Error`](struct.Error.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The format doesn't support the compression level.
+    UnsupportedLevel(Level),
+    /// The format name is unknown.
+    UnknownFormat,
+    /// Compressed data is corrupt.
+    CorruptStream,
+    /// Compressed data ends before its end mark.
+    Truncated,
+    /// Decompressed data exceeds the size or ratio limit.
+    ///
+    /// See [`ReadOptions::max_output()`](struct.ReadOptions.html#method.max_output) and [`ReadOptions::max_ratio()`](struct.ReadOptions.html#method.max_ratio).
+    LimitExceeded,
+    /// Invalid option or argument.
+    InvalidInput,
+    /// I/O error of the underlying file or stream.
+    Io,
+}
+
+impl Error {
+    pub(crate) fn new(kind: ErrorKind) -> Error {
+        Error {
+            kind,
+            path: None,
+            format: None,
+            source: None,
+        }
+    }
+
+    pub(crate) fn unsupported_level(format: Format, level: Level) -> Error {
+        Error::new(ErrorKind::UnsupportedLevel(level)).with_format(format)
+    }
+
+    pub(crate) fn invalid_input<E>(message: E) -> Error
+    where
+        E: Into<Box<dyn error::Error + Send + Sync>>,
+    {
+        Error::new(ErrorKind::InvalidInput)
+            .with_source(io::Error::new(io::ErrorKind::InvalidInput, message))
+    }
+
+    /// Classify an error returned by a decoder.
+    ///
+    /// Errors of the underlying stream are already wrapped by `SourceReader`, others are from the decoder itself.
+    /// Errors to retry on are passed through by `SourceReader`, and they are I/O errors too.
+    pub(crate) fn from_decoder(e: io::Error) -> Error {
+        if Error::from_io(&e).is_some() || is_retryable(&e) {
+            return Error::from(e);
+        }
+
+        let kind = match e.kind() {
+            io::ErrorKind::UnexpectedEof => ErrorKind::Truncated,
+            _ => ErrorKind::CorruptStream,
+        };
+        Error::new(kind).with_source(e)
+    }
+
+    /// Get this crate's error inside `std::io::Error`, if any.
+    pub fn from_io(e: &io::Error) -> Option<&Error> {
+        e.get_ref().and_then(|inner| inner.downcast_ref::<Error>())
+    }
+
+    /// Kind of this error.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Path of the file, if known.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Format of the file, if known.
+    pub fn format(&self) -> Option<Format> {
+        self.format
+    }
+
+    /// Underlying I/O error, if any.
+    pub fn io_error(&self) -> Option<&io::Error> {
+        self.source.as_ref()
+    }
+
+    pub(crate) fn with_source(mut self, source: io::Error) -> Error {
+        self.source = Some(source);
+        self
+    }
+
+    /// Set path unless already set.
+    pub(crate) fn with_path(mut self, path: &Path) -> Error {
+        if self.path.is_none() {
+            self.path = Some(path.to_path_buf());
+        }
+        self
+    }
+
+    /// Set format unless already set.
+    pub(crate) fn with_format(mut self, format: Format) -> Error {
+        if self.format.is_none() {
+            self.format = Some(format);
+        }
+        self
+    }
+
+    /// Set path and format, if known.
+    pub(crate) fn with_context(self, path: Option<&Path>, format: Option<Format>) -> Error {
+        let e = match path {
+            Some(path) => self.with_path(path),
+            None => self,
+        };
+        match format {
+            Some(format) => e.with_format(format),
+            None => e,
+        }
+    }
+
+    fn io_kind(&self) -> io::ErrorKind {
+        match self.kind {
+            ErrorKind::UnsupportedLevel(_) | ErrorKind::UnknownFormat | ErrorKind::InvalidInput => {
+                io::ErrorKind::InvalidInput
+            }
+            ErrorKind::CorruptStream | ErrorKind::LimitExceeded => io::ErrorKind::InvalidData,
+            ErrorKind::Truncated => io::ErrorKind::UnexpectedEof,
+            ErrorKind::Io => self
+                .source
+                .as_ref()
+                .map(|e| e.kind())
+                .unwrap_or(io::ErrorKind::Other),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ErrorKind::UnsupportedLevel(level) => {
+                write!(f, "unsupported compression level {:?}", level)?
+            }
+            ErrorKind::UnknownFormat => write!(f, "unknown format")?,
+            ErrorKind::CorruptStream => write!(f, "corrupt compressed stream")?,
+            ErrorKind::Truncated => write!(f, "truncated compressed stream")?,
+            ErrorKind::LimitExceeded => write!(f, "decompression limit exceeded")?,
+            ErrorKind::InvalidInput => write!(f, "invalid input")?,
+            ErrorKind::Io => write!(f, "I/O error")?,
+        }
+        if let Some(format) = self.format {
+            write!(f, " in {}", format)?;
+        }
+        if let Some(ref path) = self.path {
+            write!(f, " at {}", path.display())?;
+        }
+        if let Some(ref source) = self.source {
+            write!(f, ": {}", source)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        self.source
+            .as_ref()
+            .map(|e| e as &(dyn error::Error + 'static))
+    }
+}
+
+/// Unwraps this crate's error inside `std::io::Error`, otherwise treats it as an I/O error.
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        if Error::from_io(&e).is_some() {
+            let inner = e.into_inner().expect("checked to have inner error");
+            return *inner.downcast::<Error>().expect("checked to be Error");
+        }
+        Error::new(ErrorKind::Io).with_source(e)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        io::Error::new(e.io_kind(), e)
+    }
+}
+
+/// Wrapper of the underlying stream marks its errors as I/O errors,
+/// so that they are not mistaken for errors of decoders.
+pub(crate) struct SourceReader<R>(pub(crate) R);
+
+impl<R: Read> Read for SourceReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf).map_err(mark_io)
+    }
+}
+
+impl<R: Seek> Seek for SourceReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.0.seek(pos).map_err(mark_io)
+    }
+}
+
+pub(crate) fn mark_io(e: io::Error) -> io::Error {
+    // Retrying on these must keep working through decoders.
+    if is_retryable(&e) {
+        e
+    } else {
+        Error::from(e).into()
+    }
+}
+
+/// Whether the operation may succeed on retry, so the error must reach the caller unchanged.
+pub(crate) fn is_retryable(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+    )
+}
@@ -0,0 +1,151 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::error::{Error, ErrorKind};
+
+/// Compression format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Format {
+    /// Uncompressed
+    Plain,
+    /// Gzip (`.gz`)
+    Gzip,
+    /// LZ4 frame format (`.lz4`)
+    Lz4,
+    /// Zstandard (`.zst`, `.zstd`)
+    Zstd,
+    /// XZ (`.xz`)
+    Xz,
+    /// Legacy LZMA alone format (`.lzma`)
+    ///
+    /// This format has no magic bytes, so it is detected only from file name extension.
+    Lzma,
+    /// Bzip2 (`.bz2`)
+    Bzip2,
+    /// BGZF, blocked gzip of `bgzip` (`.bgz`)
+    ///
+    /// BGZF is valid gzip, so it is read as gzip and sniffed as [`Format::Gzip`](#variant.Gzip).
+    /// Written files can be randomly accessed by `tabix` and `samtools`.
+    /// See [`WriteOptions::bgzf_index()`](struct.WriteOptions.html#method.bgzf_index).
+    Bgzf,
+}
+
+impl Format {
+    /// Number of leading bytes [`sniff()`](#method.sniff) needs to identify every format.
+    pub const SNIFF_LEN: usize = 6;
+
+    /// Detect format from file name extension.
+    ///
+    /// Unknown extensions are [`Format::Plain`](#variant.Plain).
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Format {
+        match path.as_ref().extension() {
+            Some(e) if e == "gz" => Format::Gzip,
+            Some(e) if e == "lz4" => Format::Lz4,
+            Some(e) if e == "zst" || e == "zstd" => Format::Zstd,
+            Some(e) if e == "xz" => Format::Xz,
+            Some(e) if e == "lzma" => Format::Lzma,
+            Some(e) if e == "bz2" => Format::Bzip2,
+            Some(e) if e == "bgz" => Format::Bgzf,
+            _ => Format::Plain,
+        }
+    }
+
+    /// Detect format from leading bytes of the content.
+    ///
+    /// Returns `None` if `header` doesn't start with magic bytes of any format.
+    /// Uncompressed content has no magic bytes, so it is never detected.
+    ///
+    /// ```
+    /// use detect_compression::Format;
+    ///
+    /// assert_eq!(Format::sniff(b"BZh91AY&SY"), Some(Format::Bzip2));
+    /// assert_eq!(Format::sniff(b"BZh is a plain text line\n"), None);
+    /// ```
+    pub fn sniff(header: &[u8]) -> Option<Format> {
+        [
+            Format::Gzip,
+            Format::Lz4,
+            Format::Zstd,
+            Format::Xz,
+            Format::Bzip2,
+        ]
+        .iter()
+        .cloned()
+        .find(|&f| match f.magic() {
+            // "BZh" is common in text, so the block size digit must follow.
+            Some(magic) if f == Format::Bzip2 => {
+                header.starts_with(magic) && matches!(header.get(3), Some(b'1'..=b'9'))
+            }
+            Some(magic) => header.starts_with(magic),
+            None => false,
+        })
+    }
+
+    /// Magic bytes at the start of content in this format.
+    pub fn magic(self) -> Option<&'static [u8]> {
+        match self {
+            Format::Plain => None,
+            Format::Gzip | Format::Bgzf => Some(&[0x1f, 0x8b]),
+            Format::Lz4 => Some(&[0x04, 0x22, 0x4d, 0x18]),
+            Format::Zstd => Some(&[0x28, 0xb5, 0x2f, 0xfd]),
+            Format::Xz => Some(&[0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00]),
+            Format::Lzma => None,
+            Format::Bzip2 => Some(b"BZh"),
+        }
+    }
+
+    /// Name of this format.
+    ///
+    /// The name is accepted by [`FromStr`](https://doc.rust-lang.org/std/str/trait.FromStr.html).
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Plain => "plain",
+            Format::Gzip => "gzip",
+            Format::Lz4 => "lz4",
+            Format::Zstd => "zstd",
+            Format::Xz => "xz",
+            Format::Lzma => "lzma",
+            Format::Bzip2 => "bzip2",
+            Format::Bgzf => "bgzf",
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Parse format name or file name extension, case-insensitively.
+///
+/// ```
+/// use detect_compression::Format;
+///
+/// assert_eq!("gzip".parse::<Format>().unwrap(), Format::Gzip);
+/// assert_eq!("zst".parse::<Format>().unwrap(), Format::Zstd);
+/// ```
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Format, Error> {
+        match s.to_ascii_lowercase().as_str() {
+            "plain" | "none" => Ok(Format::Plain),
+            "gzip" | "gz" => Ok(Format::Gzip),
+            "lz4" => Ok(Format::Lz4),
+            "zstd" | "zst" => Ok(Format::Zstd),
+            "xz" => Ok(Format::Xz),
+            "lzma" => Ok(Format::Lzma),
+            "bzip2" | "bz2" => Ok(Format::Bzip2),
+            "bgzf" | "bgz" => Ok(Format::Bgzf),
+            _ => Err(
+                Error::new(ErrorKind::UnknownFormat).with_source(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("unknown format name {:?}", s),
+                )),
+            ),
+        }
+    }
+}
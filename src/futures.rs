@@ -0,0 +1,247 @@
+//! Async reader and writer for `futures-io`, independent of async runtimes.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use ::futures_io::{AsyncBufRead, AsyncRead, AsyncWrite};
+use ::futures_util::io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter, Cursor};
+use async_compression::futures::bufread::{
+    BzDecoder, GzipDecoder, Lz4Decoder, LzmaDecoder, XzDecoder, ZstdDecoder,
+};
+use async_compression::futures::write::{
+    BzEncoder, GzipEncoder, Lz4Encoder, LzmaEncoder, XzEncoder, ZstdEncoder,
+};
+
+use crate::error::{mark_io, SourceReader};
+use crate::{detect_format, read_error};
+use crate::{Error, Format, Level, ReadOptions, Result, WriteOptions};
+
+/// `futures-io` counterpart of [`DetectReader`](struct.DetectReader.html).
+///
+/// Files are opened by the async runtime, e.g. `async_std::fs::File` or `async_fs::File` for smol,
+/// and [`from_file()`](#method.from_file) detects the format from the path like [`DetectReader::open()`](struct.DetectReader.html#method.open).
+///
+/// ```no_run
+/// use detect_compression::{FuturesDetectReader, ReadOptions};
+/// use futures_util::io::AsyncReadExt;
+///
+/// # async fn run<F>(file: F) -> std::io::Result<()>
+/// # where
+/// #     F: 'static + futures_io::AsyncRead + Send + Unpin,
+/// # {
+/// // `file` is "data.log.gz" opened by the runtime.
+/// let mut reader = FuturesDetectReader::from_file(file, "data.log.gz", &ReadOptions::new()).await?;
+/// let mut s = String::new();
+/// reader.read_to_string(&mut s).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct FuturesDetectReader {
+    inner: Pin<Box<dyn AsyncBufRead + Send>>,
+    format: Format,
+    path: Option<PathBuf>,
+}
+
+impl FuturesDetectReader {
+    /// Read a file opened by the async runtime with options.
+    ///
+    /// The format is detected from `path` and the leading bytes as [`ReadOptions::open()`](struct.ReadOptions.html#method.open) does.
+    /// [`format`](struct.ReadOptions.html#method.format), [`sniff`](struct.ReadOptions.html#method.sniff)
+    /// and [`multi_member`](struct.ReadOptions.html#method.multi_member) options are used,
+    /// and [`threads`](struct.ReadOptions.html#method.threads) is ignored.
+    /// Limits and lenient truncation are not supported yet, and fail with [`ErrorKind::InvalidInput`](enum.ErrorKind.html#variant.InvalidInput).
+    pub async fn from_file<R, P>(
+        reader: R,
+        path: P,
+        options: &ReadOptions,
+    ) -> Result<FuturesDetectReader>
+    where
+        R: 'static + AsyncRead + Send + Unpin,
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        options.check_async().map_err(|e| e.with_path(path))?;
+
+        let res = match options.format {
+            Some(format) => new_reader(options, reader, format),
+            None if options.sniff => {
+                sniff_with_fallback(options, reader, Format::from_path(path)).await
+            }
+            None => new_reader(options, reader, Format::from_path(path)),
+        };
+        let mut r = res.map_err(|e| e.with_path(path))?;
+        r.path = Some(path.to_path_buf());
+        Ok(r)
+    }
+
+    /// Read compressed or uncompressed stream in the specified format.
+    pub fn from_reader<R>(reader: R, format: Format) -> Result<FuturesDetectReader>
+    where
+        R: 'static + AsyncRead + Send + Unpin,
+    {
+        new_reader(&ReadOptions::new(), reader, format)
+    }
+
+    /// Read compressed or uncompressed stream, detecting format from its leading bytes.
+    ///
+    /// If the leading bytes don't identify a format, the stream is read as uncompressed.
+    pub async fn sniff_reader<R>(reader: R) -> Result<FuturesDetectReader>
+    where
+        R: 'static + AsyncRead + Send + Unpin,
+    {
+        sniff_with_fallback(&ReadOptions::new(), reader, Format::Plain).await
+    }
+
+    /// Format of the stream.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+}
+
+impl AsyncRead for FuturesDetectReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let path = this.path.as_deref();
+        let format = this.format;
+        this.inner
+            .as_mut()
+            .poll_read(cx, buf)
+            .map_err(|e| read_error(e, path, format))
+    }
+}
+
+impl AsyncBufRead for FuturesDetectReader {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        let path = this.path.as_deref();
+        let format = this.format;
+        this.inner
+            .as_mut()
+            .poll_fill_buf(cx)
+            .map_err(|e| read_error(e, path, format))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.get_mut().inner.as_mut().consume(amt)
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for SourceReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf).map_err(mark_io)
+    }
+}
+
+/// `futures-io` counterpart of [`DetectWriter`](struct.DetectWriter.html).
+///
+/// Async drop is not possible, so [`finalize()`](#method.finalize) must be awaited to complete the output.
+/// Dropping the writer without finalize leaves incomplete output.
+///
+/// ```no_run
+/// use detect_compression::{FuturesDetectWriter, Level, WriteOptions};
+/// use futures_util::io::AsyncWriteExt;
+///
+/// # async fn run<F>(file: F) -> std::io::Result<()>
+/// # where
+/// #     F: 'static + futures_io::AsyncWrite + Send + Unpin,
+/// # {
+/// // `file` is "data.log.zst" created by the runtime.
+/// let mut writer =
+///     FuturesDetectWriter::from_file(file, "data.log.zst", Level::Default, &WriteOptions::new())?;
+/// writer.write_all(b"Hello, world!\n").await?;
+/// writer.finalize().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct FuturesDetectWriter {
+    inner: Pin<Box<dyn AsyncWrite + Send>>,
+    format: Format,
+    path: Option<PathBuf>,
+}
+
+impl FuturesDetectWriter {
+    /// Write to a file created by the async runtime with options.
+    ///
+    /// The format is detected from `path` as [`WriteOptions::create()`](struct.WriteOptions.html#method.create) does.
+    /// [`format`](struct.WriteOptions.html#method.format) and [`level_overrides`](struct.WriteOptions.html#method.level_overrides) options are used.
+    /// Atomic creation, format-specific header options, multithreading and drop policy are not supported yet,
+    /// and fail with [`ErrorKind::InvalidInput`](enum.ErrorKind.html#variant.InvalidInput).
+    pub fn from_file<W, P>(
+        writer: W,
+        path: P,
+        level: Level,
+        options: &WriteOptions,
+    ) -> Result<FuturesDetectWriter>
+    where
+        W: 'static + AsyncWrite + Send + Unpin,
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let format = options.format.unwrap_or_else(|| Format::from_path(path));
+        options
+            .check_async()
+            .map_err(|e| e.with_context(Some(path), Some(format)))?;
+
+        let mut w = new_writer(options, writer, format, level).map_err(|e| e.with_path(path))?;
+        w.path = Some(path.to_path_buf());
+        Ok(w)
+    }
+
+    /// Write to `writer` in the specified format.
+    pub fn from_writer<W>(writer: W, format: Format, level: Level) -> Result<FuturesDetectWriter>
+    where
+        W: 'static + AsyncWrite + Send + Unpin,
+    {
+        new_writer(&WriteOptions::new(), writer, format, level)
+    }
+
+    /// Format of the output.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Write the end of the compressed stream and close the underlying writer.
+    pub async fn finalize(mut self) -> Result<()> {
+        self.inner.close().await.map_err(|e| self.write_error(e))
+    }
+
+    fn write_error(&self, e: io::Error) -> Error {
+        Error::from(e).with_context(self.path.as_deref(), Some(self.format))
+    }
+}
+
+impl AsyncWrite for FuturesDetectWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let res = this.inner.as_mut().poll_write(cx, buf);
+        res.map_err(|e| this.write_error(e).into())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let res = this.inner.as_mut().poll_flush(cx);
+        res.map_err(|e| this.write_error(e).into())
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let res = this.inner.as_mut().poll_close(cx);
+        res.map_err(|e| this.write_error(e).into())
+    }
+}
+
+async_constructors!(FuturesDetectReader, FuturesDetectWriter);
@@ -0,0 +1,167 @@
+//! Gzip header metadata.
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use flate2::write::GzEncoder;
+use flate2::{Compression, GzBuilder, GzHeader};
+
+use crate::{Error, Result};
+
+/// "Unknown" operating system in gzip header.
+const OS_UNKNOWN: u8 = 255;
+
+// Header flags.
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+// Extra flags for compression level.
+const XFL_BEST: u8 = 2;
+const XFL_FAST: u8 = 4;
+
+/// Metadata in gzip header.
+///
+/// On write, set by [`WriteOptions::gzip_header()`](struct.WriteOptions.html#method.gzip_header).
+/// On read, available from [`DetectReader::gzip_header()`](struct.DetectReader.html#method.gzip_header).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GzipHeader {
+    /// Original file name, without directory.
+    pub filename: Option<Vec<u8>>,
+    /// Modification time of the original file as Unix time. `0` means not available.
+    pub mtime: u32,
+    /// Operating system where compression took place. `255` means unknown.
+    pub operating_system: u8,
+    /// Comment.
+    pub comment: Option<Vec<u8>>,
+    /// Extra field, consisting of subfields.
+    pub extra: Option<Vec<u8>>,
+}
+
+impl GzipHeader {
+    /// Create empty header.
+    pub fn new() -> GzipHeader {
+        GzipHeader {
+            filename: None,
+            mtime: 0,
+            operating_system: OS_UNKNOWN,
+            comment: None,
+            extra: None,
+        }
+    }
+
+    /// Create header with file name and modification time of the original file, like `gzip` does.
+    pub fn for_file<P: AsRef<Path>>(path: P) -> Result<GzipHeader> {
+        let path = path.as_ref();
+        let modified = fs::metadata(path)
+            .and_then(|m| m.modified())
+            .map_err(|e| Error::from(e).with_path(path))?;
+
+        let mut header = GzipHeader::new();
+        header.filename = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned().into_bytes());
+        header.mtime = modified
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs().min(u64::from(u32::MAX)) as u32)
+            .unwrap_or(0);
+        Ok(header)
+    }
+
+    pub(crate) fn encoder<W: Write>(&self, w: W, level: Compression) -> Result<GzEncoder<W>> {
+        self.validate()?;
+
+        let mut builder = GzBuilder::new()
+            .mtime(self.mtime)
+            .operating_system(self.operating_system);
+        if let Some(ref filename) = self.filename {
+            builder = builder.filename(filename.clone());
+        }
+        if let Some(ref comment) = self.comment {
+            builder = builder.comment(comment.clone());
+        }
+        if let Some(ref extra) = self.extra {
+            builder = builder.extra(extra.clone());
+        }
+        Ok(builder.write(w, level))
+    }
+
+    /// Encode header as `GzBuilder` does, for encoders writing deflate stream by themselves.
+    pub(crate) fn to_bytes(&self, level: Compression) -> Result<Vec<u8>> {
+        self.validate()?;
+
+        let mut flags = 0;
+        let mut bytes = vec![0x1f, 0x8b, 8, 0];
+        bytes.extend_from_slice(&self.mtime.to_le_bytes());
+        bytes.push(if level.level() >= Compression::best().level() {
+            XFL_BEST
+        } else if level.level() <= Compression::fast().level() {
+            XFL_FAST
+        } else {
+            0
+        });
+        bytes.push(self.operating_system);
+
+        if let Some(ref extra) = self.extra {
+            flags |= FEXTRA;
+            bytes.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(extra);
+        }
+        if let Some(ref filename) = self.filename {
+            flags |= FNAME;
+            bytes.extend_from_slice(filename);
+            bytes.push(0);
+        }
+        if let Some(ref comment) = self.comment {
+            flags |= FCOMMENT;
+            bytes.extend_from_slice(comment);
+            bytes.push(0);
+        }
+
+        bytes[3] = flags;
+        Ok(bytes)
+    }
+
+    fn validate(&self) -> Result<()> {
+        // `GzBuilder` panics on NUL in these fields.
+        if let Some(ref filename) = self.filename {
+            non_nul("file name", filename)?;
+        }
+        if let Some(ref comment) = self.comment {
+            non_nul("comment", comment)?;
+        }
+        if let Some(ref extra) = self.extra {
+            if extra.len() > usize::from(u16::MAX) {
+                return Err(Error::invalid_input("gzip extra field is too long"));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for GzipHeader {
+    fn default() -> GzipHeader {
+        GzipHeader::new()
+    }
+}
+
+impl<'a> From<&'a GzHeader> for GzipHeader {
+    fn from(h: &'a GzHeader) -> GzipHeader {
+        GzipHeader {
+            filename: h.filename().map(|v| v.to_vec()),
+            mtime: h.mtime(),
+            operating_system: h.operating_system(),
+            comment: h.comment().map(|v| v.to_vec()),
+            extra: h.extra().map(|v| v.to_vec()),
+        }
+    }
+}
+
+fn non_nul(field: &str, bytes: &[u8]) -> Result<()> {
+    if bytes.contains(&0) {
+        return Err(Error::invalid_input(format!("gzip {} contains NUL", field)));
+    }
+    Ok(())
+}
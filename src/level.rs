@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+
+use flate2::Compression;
+
+use crate::{Error, Format, Result};
+
+/// Compression level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    /// Uncompressed
+    None,
+    /// Minimum compression (fastest and large)
+    Minimum,
+    /// Default compression of each format
+    Default,
+    /// Maximum compression (smallest and slow)
+    Maximum,
+    /// Format specific level, clamped to the range each format supports.
+    ///
+    /// | Format | Range | Note |
+    /// |--------|-------|------|
+    /// | Gzip, BGZF | 0–9 | 0 is uncompressed |
+    /// | LZ4 | 0–12 | 3 and above use LZ4 HC |
+    /// | Zstandard | 1–22 | 20 and above are ultra levels |
+    /// | XZ, LZMA | 0–9 | preset |
+    /// | Bzip2 | 1–9 | block size in 100k units |
+    Precise(u32),
+}
+
+impl Level {
+    pub(crate) fn into_flate2_compression(self) -> Compression {
+        match self {
+            Level::None => Compression::none(),
+            Level::Minimum => Compression::fast(),
+            Level::Default => Compression::default(),
+            Level::Maximum => Compression::best(),
+            Level::Precise(n) => Compression::new(n.min(9)),
+        }
+    }
+
+    pub(crate) fn into_lz4_level(self) -> Result<u32> {
+        match self {
+            Level::None => Err(Error::unsupported_level(Format::Lz4, self)),
+            Level::Minimum => Ok(1),
+            // Fast mode with default acceleration.
+            Level::Default => Ok(0),
+            // LZ4HC_CLEVEL_MAX
+            Level::Maximum => Ok(12),
+            Level::Precise(n) => Ok(n.min(12)),
+        }
+    }
+
+    pub(crate) fn into_zstd_level(self) -> Result<i32> {
+        match self {
+            Level::None => Err(Error::unsupported_level(Format::Zstd, self)),
+            Level::Minimum => Ok(1),
+            Level::Default => Ok(zstd::DEFAULT_COMPRESSION_LEVEL),
+            // Highest level without `--ultra` in the zstd CLI.
+            Level::Maximum => Ok(19),
+            Level::Precise(n) => Ok(n.clamp(1, 22) as i32),
+        }
+    }
+
+    /// XZ and LZMA share presets.
+    pub(crate) fn into_xz_preset(self, format: Format) -> Result<u32> {
+        match self {
+            Level::None => Err(Error::unsupported_level(format, self)),
+            Level::Minimum => Ok(0),
+            Level::Default => Ok(6),
+            Level::Maximum => Ok(9),
+            Level::Precise(n) => Ok(n.min(9)),
+        }
+    }
+
+    /// Bzip2 level is block size in 100k units.
+    pub(crate) fn into_bzip2_compression(self) -> Result<bzip2::Compression> {
+        match self {
+            Level::None => Err(Error::unsupported_level(Format::Bzip2, self)),
+            Level::Minimum => Ok(bzip2::Compression::fast()),
+            Level::Default => Ok(bzip2::Compression::default()),
+            Level::Maximum => Ok(bzip2::Compression::best()),
+            Level::Precise(n) => Ok(bzip2::Compression::new(n.clamp(1, 9))),
+        }
+    }
+
+    /// Same level as synchronous encoders of the format.
+    #[cfg(any(feature = "tokio", feature = "futures-io"))]
+    pub(crate) fn into_async_level(self, format: Format) -> Result<async_compression::Level> {
+        let n = match format {
+            Format::Plain => 0,
+            Format::Gzip | Format::Bgzf => self.into_flate2_compression().level() as i32,
+            Format::Lz4 => self.into_lz4_level()? as i32,
+            Format::Zstd => self.into_zstd_level()?,
+            Format::Xz | Format::Lzma => self.into_xz_preset(format)? as i32,
+            Format::Bzip2 => self.into_bzip2_compression()?.level() as i32,
+        };
+        Ok(async_compression::Level::Precise(n))
+    }
+}
+
+/// Per-format compression levels overriding the level given on create.
+///
+/// ```no_run
+/// use detect_compression::{Format, Level, LevelOverrides, WriteOptions};
+///
+/// let mut levels = LevelOverrides::new();
+/// levels
+///     .set(Format::Gzip, Level::Precise(6))
+///     .set(Format::Lz4, Level::Precise(9));
+///
+/// let writer = WriteOptions::new()
+///     .level_overrides(levels)
+///     .create("out.lz4", Level::Default)?;
+/// writer.finalize()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LevelOverrides {
+    levels: HashMap<Format, Level>,
+}
+
+impl LevelOverrides {
+    /// Create empty overrides.
+    pub fn new() -> LevelOverrides {
+        LevelOverrides::default()
+    }
+
+    /// Use `level` for `format`.
+    pub fn set(&mut self, format: Format, level: Level) -> &mut LevelOverrides {
+        self.levels.insert(format, level);
+        self
+    }
+
+    /// Level for `format`, if overridden.
+    pub fn get(&self, format: Format) -> Option<Level> {
+        self.levels.get(&format).cloned()
+    }
+
+    /// Level to use for `format`, falling back to `level` if not overridden.
+    pub(crate) fn resolve(&self, format: Format, level: Level) -> Level {
+        self.get(format).unwrap_or(level)
+    }
+}
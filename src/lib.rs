@@ -1,32 +1,100 @@
 #![warn(missing_docs)]
 
-//! `BufRead` and `Write`r detects compression algorithms from file extension.
+//! `BufRead` and `Write`r detects compression algorithms from file extension or content.
 //!
 //! Supported formats:
 //! * Gzip (`.gz`) by [`flate2`](https://crates.io/crates/flate2) crate
 //! * LZ4 (`.lz4`) by [`lz4`](https://crates.io/crates/lz4) crate
+//! * Zstandard (`.zst`, `.zstd`) by [`zstd`](https://crates.io/crates/zstd) crate
+//! * XZ (`.xz`) and legacy LZMA (`.lzma`) by [`liblzma`](https://crates.io/crates/liblzma) crate
+//! * Bzip2 (`.bz2`) by [`bzip2`](https://crates.io/crates/bzip2) crate
+//! * BGZF (`.bgz`), blocked gzip of `bgzip`, written with optional `.gzi` index
+//!
+//! Optional features:
+//! * `tokio`: [`AsyncDetectReader`](struct.AsyncDetectReader.html) and [`AsyncDetectWriter`](struct.AsyncDetectWriter.html)
+//!   by [`async-compression`](https://crates.io/crates/async-compression) crate
+//! * `futures-io`: [`FuturesDetectReader`](struct.FuturesDetectReader.html) and [`FuturesDetectWriter`](struct.FuturesDetectWriter.html)
+//!   for runtimes other than tokio
 
-use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Read, Result, Write};
-use std::path::Path;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Read, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use flate2::read::GzDecoder;
+use ::lz4::{Encoder as Lz4Encoder, EncoderBuilder as Lz4EncoderBuilder};
+use bzip2::read::{BzDecoder, MultiBzDecoder};
+use bzip2::write::BzEncoder;
+use flate2::read::{GzDecoder, MultiGzDecoder};
 use flate2::write::GzEncoder;
-use flate2::Compression;
-use lz4::liblz4::ContentChecksum;
-use lz4::{Decoder as Lz4Decoder, Encoder as Lz4Encoder, EncoderBuilder as Lz4EncoderBuilder};
+use liblzma::read::XzDecoder;
+use liblzma::stream::{LzmaOptions, Stream as LzmaStream};
+use liblzma::write::XzEncoder;
+use zstd::stream::read::Decoder as ZstdDecoder;
+use zstd::stream::write::Encoder as ZstdEncoder;
+
+#[cfg(any(feature = "tokio", feature = "futures-io"))]
+#[macro_use]
+mod async_io;
+mod atomic;
+mod bgzf;
+mod count;
+mod error;
+mod format;
+#[cfg(feature = "futures-io")]
+mod futures;
+mod gzip;
+mod level;
+mod lz4;
+mod pgzip;
+mod pool;
+mod seek;
+mod stdio;
+#[cfg(feature = "tokio")]
+mod tokio;
+mod verify;
+
+pub use error::{Error, ErrorKind, Result};
+pub use format::Format;
+#[cfg(feature = "futures-io")]
+pub use futures::{FuturesDetectReader, FuturesDetectWriter};
+pub use gzip::GzipHeader;
+pub use level::{Level, LevelOverrides};
+pub use lz4::{Lz4BlockMode, Lz4BlockSize, Lz4Options};
+pub use seek::SeekableDetectReader;
+#[cfg(feature = "tokio")]
+pub use tokio::{AsyncDetectReader, AsyncDetectWriter};
+pub use verify::{verify, ChecksumStatus, Report};
+
+use atomic::AtomicFile;
+use bgzf::{BgzfReader, BgzfWriter, GziIndex, GziOutput};
+use count::{CountReader, CountWriter, Counter, Limit, LimitCounter};
+use error::{is_retryable, SourceReader};
+use lz4::Lz4Decoder;
+use pgzip::ParGzEncoder;
+use pool::thread_count;
 
 /// The [`BufRead`](https://doc.rust-lang.org/std/io/trait.BufRead.html) type reads from compressed or uncompressed file.
 ///
 /// This reader detects compression algorithms from file name extension.
+/// To detect from file content, use [`ReadOptions`](struct.ReadOptions.html).
 pub struct DetectReader {
     inner: Box<dyn BufRead>,
+    format: Format,
+    path: Option<PathBuf>,
+    gzip_header: Option<GzipHeader>,
+    limit: LimitCounter,
+    allow_truncated: bool,
+    truncation: Option<Truncation>,
 }
 
 impl DetectReader {
     /// Open compressed or uncompressed file.
+    ///
+    /// The path `"-"` means standard input, whose format is detected from leading bytes.
     pub fn open<P: AsRef<Path>>(path: P) -> Result<DetectReader> {
-        DetectReader::open_with_wrapper::<P, Id>(path, Id)
+        ReadOptions::new().open(path)
     }
 
     /// Open compressed or uncompressed file using wrapper type.
@@ -36,46 +104,477 @@ impl DetectReader {
     pub fn open_with_wrapper<P: AsRef<Path>, B: ReadWrapperBuilder>(
         path: P,
         builder: B,
+    ) -> Result<DetectReader> {
+        ReadOptions::new().open_with_wrapper(path, builder)
+    }
+
+    /// Read compressed or uncompressed stream in the specified format.
+    pub fn from_reader<R: 'static + Read>(reader: R, format: Format) -> Result<DetectReader> {
+        ReadOptions::new().from_reader(reader, format)
+    }
+
+    /// Read compressed or uncompressed stream, detecting format from its leading bytes.
+    ///
+    /// If the leading bytes don't identify a format, the stream is read as uncompressed.
+    pub fn sniff_reader<R: 'static + Read>(reader: R) -> Result<DetectReader> {
+        ReadOptions::new().sniff_reader(reader)
+    }
+
+    /// Format of the file detected on open.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Header of gzip file.
+    ///
+    /// For a file with multiple members, this is the header of the first member.
+    /// Returns `None` if the format is not gzip or the header is invalid.
+    pub fn gzip_header(&self) -> Option<&GzipHeader> {
+        self.gzip_header.as_ref()
+    }
+
+    /// Truncation of the stream, found by reading to end.
+    ///
+    /// Returns `None` unless truncated input is allowed by [`ReadOptions::allow_truncated()`](struct.ReadOptions.html#method.allow_truncated).
+    pub fn truncation(&self) -> Option<Truncation> {
+        self.truncation
+    }
+}
+
+/// Classify error from decoder and add context.
+///
+/// Errors to retry on are returned unchanged, so that callers like `read_to_end` can retry.
+fn read_error(e: io::Error, path: Option<&Path>, format: Format) -> io::Error {
+    if is_retryable(&e) {
+        return e;
+    }
+    Error::from_decoder(e)
+        .with_context(path, Some(format))
+        .into()
+}
+
+/// Whether the error is truncation of the stream.
+fn is_truncated(e: &io::Error) -> bool {
+    Error::from_io(e).is_some_and(|e| e.kind() == ErrorKind::Truncated)
+}
+
+impl Read for DetectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.truncation.is_some() {
+            return Ok(0);
+        }
+
+        let path = self.path.as_deref();
+        let format = self.format;
+        let n = match self.inner.read(buf) {
+            Ok(n) => n,
+            Err(e) => {
+                let e = read_error(e, path, format);
+                if !(self.allow_truncated && is_truncated(&e)) {
+                    return Err(e);
+                }
+                self.truncation = Some(Truncation::at(&self.limit));
+                return Ok(0);
+            }
+        };
+        self.limit
+            .check(n)
+            .map_err(|e| e.with_context(path, Some(format)))?;
+        self.limit.add(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for DetectReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.truncation.is_some() {
+            return Ok(&[]);
+        }
+
+        let path = self.path.as_deref();
+        let format = self.format;
+        let buf = match self.inner.fill_buf() {
+            Ok(buf) => buf,
+            Err(e) => {
+                let e = read_error(e, path, format);
+                if !(self.allow_truncated && is_truncated(&e)) {
+                    return Err(e);
+                }
+                self.truncation = Some(Truncation::at(&self.limit));
+                return Ok(&[]);
+            }
+        };
+        self.limit
+            .check(buf.len())
+            .map_err(|e| e.with_context(path, Some(format)))?;
+        Ok(buf)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if self.truncation.is_some() {
+            return;
+        }
+        self.limit.add(amt);
+        self.inner.consume(amt)
+    }
+}
+
+/// Truncation of a stream read in lenient mode.
+///
+/// The data lost after the truncation point is unknown, because sizes are recorded in trailers of most formats.
+/// See [`ReadOptions::allow_truncated()`](struct.ReadOptions.html#method.allow_truncated).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Truncation {
+    /// Decompressed bytes recovered before the truncation point.
+    pub decompressed: u64,
+    /// Compressed bytes read, up to the end of the file.
+    pub compressed: u64,
+}
+
+impl Truncation {
+    fn at(limit: &LimitCounter) -> Truncation {
+        Truncation {
+            decompressed: limit.bytes_out(),
+            compressed: limit.bytes_in(),
+        }
+    }
+}
+
+/// Options and flags which can be used to configure how a [`DetectReader`](struct.DetectReader.html) is opened.
+///
+/// ```no_run
+/// use detect_compression::ReadOptions;
+///
+/// // `data.log` may actually be gzip compressed.
+/// let reader = ReadOptions::new().sniff(true).open("data.log")?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct ReadOptions {
+    format: Option<Format>,
+    sniff: bool,
+    multi_member: bool,
+    threads: usize,
+    limit: Limit,
+    allow_truncated: bool,
+}
+
+impl ReadOptions {
+    /// Create default options.
+    ///
+    /// By default, compression algorithms are detected from file name extension.
+    pub fn new() -> ReadOptions {
+        ReadOptions {
+            format: None,
+            sniff: false,
+            multi_member: true,
+            threads: 1,
+            limit: Limit::default(),
+            allow_truncated: false,
+        }
+    }
+
+    /// Read the file in the specified format instead of detecting it.
+    pub fn format(&mut self, format: Format) -> &mut ReadOptions {
+        self.format = Some(format);
+        self
+    }
+
+    /// Detect compression algorithms from leading bytes of the file.
+    ///
+    /// If the leading bytes don't identify a format, the file name extension is used as a fallback.
+    /// A file whose extension names a format but whose content doesn't start with its magic bytes is read as uncompressed.
+    pub fn sniff(&mut self, sniff: bool) -> &mut ReadOptions {
+        self.sniff = sniff;
+        self
+    }
+
+    /// Read all concatenated members of the file.
+    ///
+    /// Files like `cat a.gz b.gz > c.gz` or bgzip outputs consist of multiple members.
+    /// By default, all members are decoded to end of file.
+    /// If `false`, reading stops at end of the first member.
+    ///
+    /// This applies to gzip, LZ4, Zstandard, XZ and bzip2.
+    pub fn multi_member(&mut self, multi_member: bool) -> &mut ReadOptions {
+        self.multi_member = multi_member;
+        self
+    }
+
+    /// Decode with multiple threads.
+    ///
+    /// BGZF files, gzip files of `bgzip`, are recognized by the `BC` extra subfield,
+    /// and their blocks are decoded in parallel, reading ahead of the caller.
+    /// Other files are decoded in the calling thread.
+    /// `0` means the number of available CPUs.
+    /// Default is `1`, which decodes BGZF as a plain multi-member gzip.
+    ///
+    /// ```no_run
+    /// use detect_compression::ReadOptions;
+    ///
+    /// let reader = ReadOptions::new().threads(0).open("calls.vcf.gz")?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn threads(&mut self, threads: usize) -> &mut ReadOptions {
+        self.threads = threads;
+        self
+    }
+
+    /// Allow compressed stream ending before its end mark.
+    ///
+    /// By default, reading a truncated stream fails with [`ErrorKind::Truncated`](enum.ErrorKind.html#variant.Truncated),
+    /// which converts to `std::io::ErrorKind::UnexpectedEof`.
+    /// If `true`, the reader returns the recoverable prefix and then end of file,
+    /// and [`DetectReader::truncation()`](struct.DetectReader.html#method.truncation) reports the truncation.
+    pub fn allow_truncated(&mut self, allow_truncated: bool) -> &mut ReadOptions {
+        self.allow_truncated = allow_truncated;
+        self
+    }
+
+    /// Limit total size of decompressed bytes.
+    ///
+    /// Reading fails with [`ErrorKind::LimitExceeded`](enum.ErrorKind.html#variant.LimitExceeded) as soon as the output is known to exceed `max` bytes.
+    /// By default, there is no limit.
+    pub fn max_output(&mut self, max: Option<u64>) -> &mut ReadOptions {
+        self.limit.max_output = max;
+        self
+    }
+
+    /// Limit ratio of decompressed bytes to compressed bytes.
+    ///
+    /// Compressed bytes are counted as read from the file or its wrapper, including the format's headers.
+    /// Reading fails with [`ErrorKind::LimitExceeded`](enum.ErrorKind.html#variant.LimitExceeded) when the ratio so far exceeds `max`.
+    /// By default, there is no limit.
+    ///
+    /// This protects against decompression bombs, small files expanding to huge data.
+    pub fn max_ratio(&mut self, max: Option<f64>) -> &mut ReadOptions {
+        self.limit.max_ratio = max;
+        self
+    }
+
+    /// Open compressed or uncompressed file with these options.
+    ///
+    /// The path `"-"` means standard input.
+    /// Standard input has no extension, so its format is always detected from leading bytes unless [`format`](#method.format) is specified.
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> Result<DetectReader> {
+        self.open_with_wrapper::<P, Id>(path, Id)
+    }
+
+    /// Open compressed or uncompressed file using wrapper type with these options.
+    ///
+    /// See [`DetectReader::open_with_wrapper()`](struct.DetectReader.html#method.open_with_wrapper).
+    pub fn open_with_wrapper<P: AsRef<Path>, B: ReadWrapperBuilder>(
+        &self,
+        path: P,
+        builder: B,
     ) -> Result<DetectReader> {
         let path = path.as_ref();
 
-        let f = File::open(path)?;
-        let wf = builder.new_wrapped_reader(f);
+        let is_stdin = stdio::is_stdio(path);
+        let f = if is_stdin {
+            stdio::stdin()
+        } else {
+            File::open(path)
+        };
+        let wf = builder.new_wrapped_reader(f.map_err(|e| Error::from(e).with_path(path))?);
+
+        let res = match self.format {
+            Some(format) => self.new_reader(wf, format),
+            None if is_stdin => self.sniff_with_fallback(wf, Format::Plain),
+            None if self.sniff => self.sniff_with_fallback(wf, Format::from_path(path)),
+            None => self.new_reader(wf, Format::from_path(path)),
+        };
+        let mut r = res.map_err(|e| e.with_path(path))?;
+        r.path = Some(path.to_path_buf());
+        Ok(r)
+    }
+
+    /// Decode whole file and check its checksums with these options.
+    ///
+    /// See [`verify()`](fn.verify.html).
+    pub fn verify<P: AsRef<Path>>(&self, path: P) -> Result<Report> {
+        verify::run(self, path.as_ref())
+    }
+
+    /// Read compressed or uncompressed stream in the specified format with these options.
+    ///
+    /// [`format`](#method.format) and [`sniff`](#method.sniff) options are ignored.
+    pub fn from_reader<R: 'static + Read>(
+        &self,
+        reader: R,
+        format: Format,
+    ) -> Result<DetectReader> {
+        self.new_reader(reader, format)
+    }
+
+    /// Read compressed or uncompressed stream, detecting format from its leading bytes, with these options.
+    ///
+    /// [`format`](#method.format) option is ignored.
+    ///
+    /// If the leading bytes don't identify a format, the stream is read as uncompressed.
+    pub fn sniff_reader<R: 'static + Read>(&self, reader: R) -> Result<DetectReader> {
+        self.sniff_with_fallback(reader, Format::Plain)
+    }
+
+    fn sniff_with_fallback<R: 'static + Read>(
+        &self,
+        mut r: R,
+        fallback: Format,
+    ) -> Result<DetectReader> {
+        let mut header = [0u8; Format::SNIFF_LEN];
+        let n = read_header(&mut r, &mut header)?;
+        let header = &header[..n];
+
+        let format = detect_format(header, fallback);
+        self.new_reader(Cursor::new(header.to_vec()).chain(r), format)
+    }
+
+    /// Reject options async readers don't support, rather than ignoring them silently.
+    #[cfg(any(feature = "tokio", feature = "futures-io"))]
+    fn check_async(&self) -> Result<()> {
+        let limited = self.limit.max_output.is_some() || self.limit.max_ratio.is_some();
+        if limited || self.allow_truncated {
+            return Err(Error::invalid_input(
+                "decompression limits and lenient truncation are not supported by async readers",
+            ));
+        }
+        Ok(())
+    }
+
+    fn new_reader<R: 'static + Read>(&self, r: R, format: Format) -> Result<DetectReader> {
+        let mut gzip_header = None;
+        let bytes_in = Counter::default();
+        let r = SourceReader(CountReader::new(r, bytes_in.clone()));
+        let inner = self
+            .new_decoder(r, format, &mut gzip_header)
+            .map_err(|e| Error::from_decoder(e).with_format(format))?;
+
+        Ok(DetectReader {
+            inner,
+            format,
+            path: None,
+            gzip_header,
+            limit: LimitCounter::new(self.limit, bytes_in),
+            allow_truncated: self.allow_truncated,
+            truncation: None,
+        })
+    }
 
-        let inner: Box<dyn BufRead> = match path.extension() {
-            Some(e) if e == "gz" => {
-                let d = GzDecoder::new(wf);
+    fn new_decoder<R: 'static + Read>(
+        &self,
+        r: R,
+        format: Format,
+        gzip_header: &mut Option<GzipHeader>,
+    ) -> io::Result<Box<dyn BufRead>> {
+        let inner: Box<dyn BufRead> = match format {
+            Format::Gzip | Format::Bgzf if self.multi_member && thread_count(self.threads) > 1 => {
+                let (header, r) = bgzf::peek_header(r)?;
+                match header {
+                    Some(header) => {
+                        *gzip_header = Some(header);
+                        Box::new(BgzfReader::new(r, thread_count(self.threads)))
+                    }
+                    None => {
+                        let d = MultiGzDecoder::new(r);
+                        *gzip_header = d.header().map(GzipHeader::from);
+                        let br = BufReader::new(d);
+                        Box::new(br)
+                    }
+                }
+            }
+            Format::Gzip | Format::Bgzf if self.multi_member => {
+                let d = MultiGzDecoder::new(r);
+                *gzip_header = d.header().map(GzipHeader::from);
+                let br = BufReader::new(d);
+                Box::new(br)
+            }
+            Format::Gzip | Format::Bgzf => {
+                let d = GzDecoder::new(r);
+                *gzip_header = d.header().map(GzipHeader::from);
+                let br = BufReader::new(d);
+                Box::new(br)
+            }
+            Format::Lz4 => {
+                let d = Lz4Decoder::new(r, self.multi_member)?;
+                let br = BufReader::new(d);
+                Box::new(br)
+            }
+            Format::Zstd => {
+                let mut d = ZstdDecoder::new(r)?;
+                if !self.multi_member {
+                    d = d.single_frame();
+                }
+                let br = BufReader::new(d);
+                Box::new(br)
+            }
+            Format::Xz if self.multi_member => {
+                let d = XzDecoder::new_multi_decoder(r);
+                let br = BufReader::new(d);
+                Box::new(br)
+            }
+            Format::Xz => {
+                let d = XzDecoder::new(r);
+                let br = BufReader::new(d);
+                Box::new(br)
+            }
+            Format::Lzma => {
+                let s = LzmaStream::new_lzma_decoder(u64::MAX)?;
+                let d = XzDecoder::new_stream(r, s);
+                let br = BufReader::new(d);
+                Box::new(br)
+            }
+            Format::Bzip2 if self.multi_member => {
+                let d = MultiBzDecoder::new(r);
                 let br = BufReader::new(d);
                 Box::new(br)
             }
-            Some(e) if e == "lz4" => {
-                let d = Lz4Decoder::new(wf)?;
+            Format::Bzip2 => {
+                let d = BzDecoder::new(r);
                 let br = BufReader::new(d);
                 Box::new(br)
             }
-            _ => {
-                let br = BufReader::new(wf);
+            Format::Plain => {
+                let br = BufReader::new(r);
                 Box::new(br)
             }
         };
 
-        Ok(DetectReader { inner })
+        Ok(inner)
     }
 }
 
-impl Read for DetectReader {
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        self.inner.read(buf)
+impl Default for ReadOptions {
+    fn default() -> ReadOptions {
+        ReadOptions::new()
     }
 }
 
-impl BufRead for DetectReader {
-    fn fill_buf(&mut self) -> Result<&[u8]> {
-        self.inner.fill_buf()
+/// Read leading bytes up to `buf.len()`, stopping early only at end of stream.
+pub(crate) fn read_header<R: Read>(r: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match r.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(m) => n += m,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
     }
+    Ok(n)
+}
 
-    fn consume(&mut self, amt: usize) {
-        self.inner.consume(amt)
+/// Choose format from leading bytes, falling back to `fallback` (usually from extension) when the bytes are ambiguous.
+fn detect_format(header: &[u8], fallback: Format) -> Format {
+    if let Some(format) = Format::sniff(header) {
+        return format;
+    }
+
+    match fallback.magic() {
+        // Too short to tell. Let the decoder report the error.
+        Some(magic) if magic.starts_with(header) => fallback,
+        // Has magic bytes, but they don't match.
+        Some(_) => Format::Plain,
+        None => fallback,
     }
 }
 
@@ -84,15 +583,29 @@ impl BufRead for DetectReader {
 /// This writer detects compression algorithms from file name extension.
 ///
 /// You must [`finalize`](struct.DetectWriter.html#method.finalize) this writer.
+/// What happens if it is dropped before finalization is configured by [`DropPolicy`](enum.DropPolicy.html).
 pub struct DetectWriter {
     inner: Box<dyn Finalize>,
+    format: Format,
+    level: Level,
+    drop_policy: DropPolicy,
+    output: Output,
+    path: Option<PathBuf>,
+    bytes_in: u64,
+    bytes_out: Counter,
+    started: Instant,
+    /// `.gzi` index written after the file.
+    index: Option<GziOutput>,
     not_closed: bool,
 }
 
 impl DetectWriter {
     /// Create compressed or uncompressed file.
+    ///
+    /// The path `"-"` means standard output, which is written uncompressed.
+    /// To compress standard output, use [`WriteOptions::format()`](struct.WriteOptions.html#method.format).
     pub fn create<P: AsRef<Path>>(path: P, level: Level) -> Result<DetectWriter> {
-        DetectWriter::create_with_wrapper::<P, Id>(path, level, Id)
+        WriteOptions::new().create(path, level)
     }
 
     /// Create compressed or uncompressed file using wrapper type.
@@ -104,95 +617,529 @@ impl DetectWriter {
         level: Level,
         builder: B,
     ) -> Result<DetectWriter> {
-        let path = path.as_ref();
-
-        let f = File::create(path)?;
-        let wf = builder.new_wrapped_writer(f);
-        let w = BufWriter::new(wf);
-
-        let inner: Box<dyn Finalize> = match path.extension() {
-            Some(e) if e == "gz" => {
-                let e = GzEncoder::new(w, level.into_flate2_compression());
-                Box::new(e)
-            }
-            Some(e) if e == "lz4" => {
-                let mut builder = Lz4EncoderBuilder::new();
-                builder
-                    .level(level.into_lz4_level()?)
-                    .checksum(ContentChecksum::ChecksumEnabled);
+        WriteOptions::new().create_with_wrapper(path, level, builder)
+    }
 
-                let e = builder.build(w)?;
-                Box::new(FinalizeLz4Encoder::new(e))
-            }
-            _ => Box::new(w),
-        };
+    /// Write compressed or uncompressed stream in the specified format.
+    pub fn from_writer<W: 'static + Write>(
+        writer: W,
+        format: Format,
+        level: Level,
+    ) -> Result<DetectWriter> {
+        WriteOptions::new().from_writer(writer, format, level)
+    }
 
-        Ok(DetectWriter {
-            inner,
-            not_closed: true,
-        })
+    /// Format of the file detected on create.
+    pub fn format(&self) -> Format {
+        self.format
     }
 
     /// Finalize this writer.
     ///
     /// Some encodings requires finalization.
+    /// This writes the trailer of the format and flushes all buffered data, reporting any error.
     ///
-    pub fn finalize(mut self) -> Result<()> {
-        if self.not_closed {
-            self.inner.finalize()?;
-            self.not_closed = false;
+    /// In [atomic](struct.WriteOptions.html#method.atomic) mode, the file is synced and renamed into place after that.
+    ///
+    /// Returns the [`Summary`](struct.Summary.html) of written data.
+    pub fn finalize(mut self) -> Result<Summary> {
+        // Even if finalization fails, the drop policy should not apply. The caller gets the error.
+        self.not_closed = false;
+        let res = self.inner.finalize();
+        // Close the file before removing or renaming it.
+        self.close_inner();
+        let res = match mem::replace(&mut self.output, Output::Stream) {
+            Output::Atomic(atomic) if res.is_ok() => atomic.commit(),
+            // Dropping removes the temporary file.
+            _ => res,
+        };
+        let res = res.and_then(|_| self.write_index());
+        res.map_err(|e| self.write_error(e))?;
+
+        Ok(Summary {
+            format: self.format,
+            level: self.level,
+            bytes_in: self.bytes_in,
+            bytes_out: self.bytes_out.get(),
+            elapsed: self.started.elapsed(),
+        })
+    }
+
+    fn write_error(&self, e: io::Error) -> Error {
+        Error::from(e).with_context(self.path.as_deref(), Some(self.format))
+    }
+
+    /// Write `.gzi` index, if requested.
+    fn write_index(&mut self) -> io::Result<()> {
+        match self.index.take() {
+            Some(index) => index.write(),
+            None => Ok(()),
         }
-        Ok(())
+    }
+
+    /// Close the underlying file.
+    fn close_inner(&mut self) {
+        drop(mem::replace(&mut self.inner, Box::new(io::sink())));
     }
 }
 
 impl Write for DetectWriter {
-    fn write(&mut self, bytes: &[u8]) -> Result<usize> {
-        self.inner.write(bytes)
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(bytes).map_err(|e| self.write_error(e))?;
+        self.bytes_in += n as u64;
+        Ok(n)
     }
 
-    fn flush(&mut self) -> Result<()> {
-        self.inner.flush()
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush().map_err(|e| self.write_error(e).into())
     }
 }
 
 impl Drop for DetectWriter {
     fn drop(&mut self) {
-        if self.not_closed {
-            panic!("DetectWriter must be finalized. But dropped before finalization.");
+        if !self.not_closed {
+            return;
+        }
+
+        let res = match self.drop_policy {
+            DropPolicy::Finish => self.inner.finalize(),
+            _ => Ok(()),
+        };
+        // Close the file before removing or renaming it.
+        self.close_inner();
+
+        // Temporary file of atomic mode is removed by dropping, unless committed.
+        match (
+            self.drop_policy,
+            mem::replace(&mut self.output, Output::Stream),
+        ) {
+            // Panic while unwinding aborts the process.
+            (DropPolicy::Panic, _) if !thread::panicking() => {
+                panic!("DetectWriter must be finalized. But dropped before finalization.");
+            }
+            (DropPolicy::Finish, Output::Atomic(atomic)) if res.is_ok() => {
+                let _ = atomic.commit().and_then(|_| self.write_index());
+            }
+            (DropPolicy::Finish, _) if res.is_ok() => {
+                let _ = self.write_index();
+            }
+            (DropPolicy::Discard, Output::File(path)) => {
+                let _ = fs::remove_file(path);
+            }
+            _ => {}
         }
     }
 }
 
-/// Compression level.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub enum Level {
-    /// Uncompressed
-    None,
-    /// Minimum compression (fastest and large)
-    Minimum,
-    /// Maximum compression (smallest and slow)
-    Maximum,
+/// Summary of data written by [`DetectWriter`](struct.DetectWriter.html).
+///
+/// Returned by [`DetectWriter::finalize()`](struct.DetectWriter.html#method.finalize).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Summary {
+    /// Format of the output.
+    pub format: Format,
+    /// Compression level of the output.
+    pub level: Level,
+    /// Uncompressed bytes written to the writer.
+    pub bytes_in: u64,
+    /// Compressed bytes written to the file.
+    pub bytes_out: u64,
+    /// Time from creation to finalization.
+    pub elapsed: Duration,
+}
+
+impl Summary {
+    /// Compression ratio, compressed size divided by uncompressed size.
+    ///
+    /// Returns `None` if nothing was written.
+    pub fn ratio(&self) -> Option<f64> {
+        if self.bytes_in == 0 {
+            None
+        } else {
+            Some(self.bytes_out as f64 / self.bytes_in as f64)
+        }
+    }
+}
+
+/// Destination of [`DetectWriter`](struct.DetectWriter.html).
+enum Output {
+    /// Standard output or user supplied writer.
+    Stream,
+    /// File created at the path.
+    File(PathBuf),
+    /// Temporary file to be renamed on finalization.
+    Atomic(AtomicFile),
+}
+
+/// What [`DetectWriter`](struct.DetectWriter.html) does when dropped before finalization.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DropPolicy {
+    /// Panic. Errors are never missed, but early returns by `?` abort the program.
+    ///
+    /// If the thread is already panicking, the writer is dropped silently to avoid a double panic.
+    #[default]
+    Panic,
+    /// Finalize the writer, ignoring errors.
+    Finish,
+    /// Stop writing and remove the partially written file.
+    ///
+    /// Standard output and user supplied writers are left as they are.
+    Discard,
+}
+
+/// Options and flags which can be used to configure how a [`DetectWriter`](struct.DetectWriter.html) is created.
+///
+/// ```no_run
+/// use detect_compression::{Format, Level, WriteOptions};
+///
+/// // Write gzip compressed data to standard output.
+/// let writer = WriteOptions::new()
+///     .format(Format::Gzip)
+///     .create("-", Level::Minimum)?;
+/// writer.finalize()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct WriteOptions {
+    format: Option<Format>,
+    drop_policy: DropPolicy,
+    atomic: bool,
+    sync_dir: bool,
+    level_overrides: LevelOverrides,
+    lz4_options: Lz4Options,
+    gzip_header: GzipHeader,
+    threads: usize,
+    block_size: usize,
+    bgzf_index: bool,
 }
 
-impl Level {
-    fn into_flate2_compression(self) -> Compression {
-        match self {
-            Level::None => Compression::none(),
-            Level::Minimum => Compression::fast(),
-            Level::Maximum => Compression::best(),
+impl WriteOptions {
+    /// Create default options.
+    ///
+    /// By default, compression algorithms are detected from file name extension.
+    pub fn new() -> WriteOptions {
+        WriteOptions {
+            format: None,
+            drop_policy: DropPolicy::default(),
+            atomic: false,
+            sync_dir: false,
+            level_overrides: LevelOverrides::new(),
+            lz4_options: Lz4Options::new(),
+            gzip_header: GzipHeader::new(),
+            threads: 1,
+            block_size: 128 * 1024,
+            bgzf_index: false,
         }
     }
 
-    fn into_lz4_level(self) -> Result<u32> {
-        match self {
-            Level::None => Err(Error::new(
-                ErrorKind::InvalidInput,
-                "LZ4 don't support non-compression mode",
-            )),
-            Level::Minimum => Ok(1),
-            Level::Maximum => Ok(3),
+    /// Write the file in the specified format instead of detecting it from file name extension.
+    pub fn format(&mut self, format: Format) -> &mut WriteOptions {
+        self.format = Some(format);
+        self
+    }
+
+    /// Set behavior when the writer is dropped before finalization.
+    ///
+    /// Default is [`DropPolicy::Panic`](enum.DropPolicy.html#variant.Panic).
+    pub fn drop_policy(&mut self, drop_policy: DropPolicy) -> &mut WriteOptions {
+        self.drop_policy = drop_policy;
+        self
+    }
+
+    /// Use per-format compression levels instead of the level given on create.
+    pub fn level_overrides(&mut self, level_overrides: LevelOverrides) -> &mut WriteOptions {
+        self.level_overrides = level_overrides;
+        self
+    }
+
+    /// Set frame parameters of LZ4 output.
+    pub fn lz4_options(&mut self, lz4_options: Lz4Options) -> &mut WriteOptions {
+        self.lz4_options = lz4_options;
+        self
+    }
+
+    /// Set metadata in header of gzip output.
+    ///
+    /// ```no_run
+    /// use detect_compression::{GzipHeader, Level, WriteOptions};
+    ///
+    /// // Keep original file name and modification time, like `gzip`.
+    /// let writer = WriteOptions::new()
+    ///     .gzip_header(GzipHeader::for_file("data.csv")?)
+    ///     .create("data.csv.gz", Level::Default)?;
+    /// writer.finalize()?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn gzip_header(&mut self, gzip_header: GzipHeader) -> &mut WriteOptions {
+        self.gzip_header = gzip_header;
+        self
+    }
+
+    /// Compress with multiple threads, like `pigz`.
+    ///
+    /// Input is split into [blocks](#method.block_size) compressed in parallel,
+    /// and the output is a standard gzip stream of a single member.
+    /// `0` means the number of available CPUs.
+    /// Default is `1`, which uses the single-threaded encoder.
+    ///
+    /// This applies to gzip and BGZF.
+    /// BGZF output is made of independent blocks of 64 KiB, so [`block_size`](#method.block_size) doesn't apply to it.
+    ///
+    /// ```no_run
+    /// use detect_compression::{Level, WriteOptions};
+    ///
+    /// let writer = WriteOptions::new()
+    ///     .threads(0)
+    ///     .create("export.csv.gz", Level::Default)?;
+    /// writer.finalize()?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn threads(&mut self, threads: usize) -> &mut WriteOptions {
+        self.threads = threads;
+        self
+    }
+
+    /// Set size of uncompressed blocks for multithreaded compression.
+    ///
+    /// Smaller blocks use less memory, larger blocks compress better.
+    /// Default is 128 KiB.
+    pub fn block_size(&mut self, block_size: usize) -> &mut WriteOptions {
+        self.block_size = block_size;
+        self
+    }
+
+    /// Write `.gzi` index of BGZF output on [`finalize`](struct.DetectWriter.html#method.finalize).
+    ///
+    /// The index is written to the path with `.gzi` appended, like `bgzip --index`.
+    /// It lists offsets of blocks for random access by `samtools faidx` and similar tools.
+    /// In [atomic](#method.atomic) mode, the index is also created atomically after the file.
+    ///
+    /// Creating BGZF output with the index fails on standard output and user supplied writers.
+    ///
+    /// ```no_run
+    /// use detect_compression::{Level, WriteOptions};
+    ///
+    /// // Writes "genome.fa.bgz" and "genome.fa.bgz.gzi".
+    /// let writer = WriteOptions::new()
+    ///     .bgzf_index(true)
+    ///     .create("genome.fa.bgz", Level::Default)?;
+    /// writer.finalize()?;
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn bgzf_index(&mut self, bgzf_index: bool) -> &mut WriteOptions {
+        self.bgzf_index = bgzf_index;
+        self
+    }
+
+    /// Create the file atomically.
+    ///
+    /// Data is written to a temporary file in the same directory.
+    /// On [`finalize`](struct.DetectWriter.html#method.finalize), the temporary file is synced to disk and renamed to the path,
+    /// so the path never has a partially written file.
+    /// If the writer is dropped before finalization, the temporary file is removed,
+    /// unless [`DropPolicy::Finish`](enum.DropPolicy.html#variant.Finish) finishes it.
+    ///
+    /// This has no effect on standard output.
+    pub fn atomic(&mut self, atomic: bool) -> &mut WriteOptions {
+        self.atomic = atomic;
+        self
+    }
+
+    /// Sync the parent directory after renaming in [atomic](#method.atomic) mode.
+    ///
+    /// This makes the rename itself durable. It is supported only on Unix.
+    pub fn sync_dir(&mut self, sync_dir: bool) -> &mut WriteOptions {
+        self.sync_dir = sync_dir;
+        self
+    }
+
+    /// Create compressed or uncompressed file with these options.
+    ///
+    /// The path `"-"` means standard output.
+    /// Standard output has no extension, so it is uncompressed unless [`format`](#method.format) is specified.
+    pub fn create<P: AsRef<Path>>(&self, path: P, level: Level) -> Result<DetectWriter> {
+        self.create_with_wrapper::<P, Id>(path, level, Id)
+    }
+
+    /// Create compressed or uncompressed file using wrapper type with these options.
+    ///
+    /// See [`DetectWriter::create_with_wrapper()`](struct.DetectWriter.html#method.create_with_wrapper).
+    pub fn create_with_wrapper<P: AsRef<Path>, B: WriteWrapperBuilder>(
+        &self,
+        path: P,
+        level: Level,
+        builder: B,
+    ) -> Result<DetectWriter> {
+        let path = path.as_ref();
+        let format = self.format.unwrap_or_else(|| Format::from_path(path));
+
+        let index = if self.wants_index(format) {
+            if stdio::is_stdio(path) {
+                return Err(Error::invalid_input(
+                    "BGZF index can't be written for standard output",
+                )
+                .with_context(Some(path), Some(format)));
+            }
+            Some(GziOutput::new(path, self.atomic, self.sync_dir))
+        } else {
+            None
+        };
+
+        let opened = if stdio::is_stdio(path) {
+            stdio::stdout().map(|f| (f, Output::Stream))
+        } else if self.atomic {
+            AtomicFile::create(path, self.sync_dir).map(|(f, atomic)| (f, Output::Atomic(atomic)))
+        } else {
+            File::create(path).map(|f| (f, Output::File(path.to_path_buf())))
+        };
+        let (f, output) =
+            opened.map_err(|e| Error::from(e).with_context(Some(path), Some(format)))?;
+        let wf = builder.new_wrapped_writer(f);
+
+        let mut w = self
+            .new_writer(wf, format, level, output, index)
+            .map_err(|e| e.with_path(path))?;
+        w.path = Some(path.to_path_buf());
+        Ok(w)
+    }
+
+    /// Write compressed or uncompressed stream in the specified format with these options.
+    ///
+    /// [`format`](#method.format) option is ignored.
+    pub fn from_writer<W: 'static + Write>(
+        &self,
+        writer: W,
+        format: Format,
+        level: Level,
+    ) -> Result<DetectWriter> {
+        if self.wants_index(format) {
+            return Err(Error::invalid_input(
+                "BGZF index can't be written for user supplied writer",
+            )
+            .with_format(format));
         }
+        self.new_writer(writer, format, level, Output::Stream, None)
+    }
+
+    fn wants_index(&self, format: Format) -> bool {
+        format == Format::Bgzf && self.bgzf_index
+    }
+
+    /// Reject options async writers don't support, rather than ignoring them silently.
+    #[cfg(any(feature = "tokio", feature = "futures-io"))]
+    fn check_async(&self) -> Result<()> {
+        let default = WriteOptions::new();
+        let message = if self.atomic || self.sync_dir {
+            "atomic creation is not supported by async writers"
+        } else if self.lz4_options != default.lz4_options || self.gzip_header != default.gzip_header
+        {
+            "format-specific header options are not supported by async writers"
+        } else if self.threads != default.threads || self.block_size != default.block_size {
+            "multithreaded compression is not supported by async writers"
+        } else if self.drop_policy != default.drop_policy {
+            "drop policy is not supported by async writers, which must be finalized"
+        } else {
+            return Ok(());
+        };
+        Err(Error::invalid_input(message))
+    }
+
+    fn new_writer<W: 'static + Write>(
+        &self,
+        writer: W,
+        format: Format,
+        level: Level,
+        output: Output,
+        index: Option<GziOutput>,
+    ) -> Result<DetectWriter> {
+        let level = self.level_overrides.resolve(format, level);
+        let bytes_out = Counter::default();
+        let w = BufWriter::new(CountWriter::new(writer, bytes_out.clone()));
+
+        let inner = self
+            .new_encoder(w, format, level, index.as_ref().map(|i| i.index.clone()))
+            .map_err(|e| e.with_format(format))?;
+
+        Ok(DetectWriter {
+            inner,
+            format,
+            level,
+            drop_policy: self.drop_policy,
+            output,
+            path: None,
+            bytes_in: 0,
+            bytes_out,
+            started: Instant::now(),
+            index,
+            not_closed: true,
+        })
+    }
+
+    fn new_encoder<W: 'static + Write>(
+        &self,
+        w: BufWriter<W>,
+        format: Format,
+        level: Level,
+        index: Option<GziIndex>,
+    ) -> Result<Box<dyn Finalize>> {
+        let inner: Box<dyn Finalize> = match format {
+            Format::Bgzf => {
+                let level = level.into_flate2_compression();
+                let e = BgzfWriter::new(w, level, thread_count(self.threads), index);
+                Box::new(e)
+            }
+            Format::Gzip if thread_count(self.threads) > 1 => {
+                let level = level.into_flate2_compression();
+                let header = self.gzip_header.to_bytes(level)?;
+                let threads = thread_count(self.threads);
+                let e = ParGzEncoder::new(w, &header, level, threads, self.block_size)?;
+                Box::new(e)
+            }
+            Format::Gzip => {
+                let e = self
+                    .gzip_header
+                    .encoder(w, level.into_flate2_compression())?;
+                Box::new(e)
+            }
+            Format::Lz4 => {
+                let mut builder = Lz4EncoderBuilder::new();
+                builder.level(level.into_lz4_level()?);
+                self.lz4_options.apply(&mut builder);
+
+                let e = builder.build(w)?;
+                Box::new(FinalizeLz4Encoder::new(e))
+            }
+            Format::Zstd => {
+                let mut e = ZstdEncoder::new(w, level.into_zstd_level()?)?;
+                // Like the zstd command, so that corruption is detected.
+                e.include_checksum(true)?;
+                Box::new(e)
+            }
+            Format::Xz => {
+                let e = XzEncoder::new(w, level.into_xz_preset(format)?);
+                Box::new(e)
+            }
+            Format::Lzma => {
+                let options = LzmaOptions::new_preset(level.into_xz_preset(format)?)
+                    .map_err(io::Error::from)?;
+                let s = LzmaStream::new_lzma_encoder(&options).map_err(io::Error::from)?;
+                let e = XzEncoder::new_stream(w, s);
+                Box::new(e)
+            }
+            Format::Bzip2 => {
+                let e = BzEncoder::new(w, level.into_bzip2_compression()?);
+                Box::new(e)
+            }
+            Format::Plain => Box::new(w),
+        };
+
+        Ok(inner)
+    }
+}
+
+impl Default for WriteOptions {
+    fn default() -> WriteOptions {
+        WriteOptions::new()
     }
 }
 
@@ -234,15 +1181,57 @@ impl WriteWrapperBuilder for Id {
 }
 
 trait Finalize: Write {
-    fn finalize(&mut self) -> Result<()> {
+    fn finalize(&mut self) -> io::Result<()> {
         self.flush()
     }
 }
 
 impl Finalize for File {}
-impl<W: Write> Finalize for GzEncoder<W> {}
+impl Finalize for io::Sink {}
+impl<W: Write> Finalize for GzEncoder<W> {
+    fn finalize(&mut self) -> io::Result<()> {
+        // Write trailer here. `Drop` of `GzEncoder` also writes it, but ignores errors.
+        self.try_finish()?;
+        self.get_mut().flush()
+    }
+}
 impl<W: Write> Finalize for BufWriter<W> {}
 
+impl<W: Write> Finalize for ParGzEncoder<W> {
+    fn finalize(&mut self) -> io::Result<()> {
+        self.try_finish()?;
+        self.get_mut().flush()
+    }
+}
+
+impl<W: Write> Finalize for BgzfWriter<W> {
+    fn finalize(&mut self) -> io::Result<()> {
+        self.try_finish()?;
+        self.get_mut().flush()
+    }
+}
+
+impl<W: Write> Finalize for BzEncoder<W> {
+    fn finalize(&mut self) -> io::Result<()> {
+        self.try_finish()?;
+        self.get_mut().flush()
+    }
+}
+
+impl<W: Write> Finalize for XzEncoder<W> {
+    fn finalize(&mut self) -> io::Result<()> {
+        self.try_finish()?;
+        self.get_mut().flush()
+    }
+}
+
+impl<W: Write> Finalize for ZstdEncoder<'static, W> {
+    fn finalize(&mut self) -> io::Result<()> {
+        self.do_finish()?;
+        self.get_mut().flush()
+    }
+}
+
 struct FinalizeLz4Encoder<W: Write>(Option<Lz4Encoder<W>>);
 
 impl<W: Write> FinalizeLz4Encoder<W> {
@@ -252,22 +1241,193 @@ impl<W: Write> FinalizeLz4Encoder<W> {
 }
 
 impl<W: Write> Write for FinalizeLz4Encoder<W> {
-    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         self.0
             .as_mut()
             .expect("writer already finalized")
             .write(buf)
     }
 
-    fn flush(&mut self) -> Result<()> {
+    fn flush(&mut self) -> io::Result<()> {
         self.0.as_mut().expect("writer already finalized").flush()
     }
 }
 
 impl<W: Write> Finalize for FinalizeLz4Encoder<W> {
-    fn finalize(&mut self) -> Result<()> {
+    fn finalize(&mut self) -> io::Result<()> {
         self.flush()?;
         let enc = self.0.take().expect("writer already finalized");
-        enc.finish().1
+        let (mut w, res) = enc.finish();
+        res?;
+        w.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::{self, BufRead, Cursor, Read, Write};
+    use std::rc::Rc;
+
+    use super::{DetectReader, Format, Level, ReadOptions, WriteOptions};
+
+    /// Compressed formats, with the number of threads to decode them.
+    const COMPRESSED: [(Format, usize); 8] = [
+        (Format::Gzip, 1),
+        (Format::Bgzf, 1),
+        (Format::Bgzf, 4),
+        (Format::Lz4, 1),
+        (Format::Zstd, 1),
+        (Format::Xz, 1),
+        (Format::Lzma, 1),
+        (Format::Bzip2, 1),
+    ];
+
+    /// Formats whose files can be concatenated.
+    const CONCATENATED: [Format; 6] = [
+        Format::Gzip,
+        Format::Bgzf,
+        Format::Lz4,
+        Format::Zstd,
+        Format::Xz,
+        Format::Bzip2,
+    ];
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| (i * 7 % 251) as u8 ^ (i / 1000) as u8)
+            .collect()
+    }
+
+    /// Output kept after the writer is finalized.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn compress(data: &[u8], format: Format) -> Vec<u8> {
+        let out = Shared::default();
+        let mut w = WriteOptions::new()
+            .from_writer(out.clone(), format, Level::Default)
+            .unwrap();
+        w.write_all(data).unwrap();
+        w.finalize().unwrap();
+        out.0.take()
+    }
+
+    fn decode(file: &[u8], format: Format, options: &ReadOptions) -> io::Result<Vec<u8>> {
+        let mut r = options.from_reader(Cursor::new(file.to_vec()), format)?;
+        let mut out = Vec::new();
+        r.read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    fn read_buffered(r: &mut DetectReader) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        loop {
+            let buf = r.fill_buf()?;
+            if buf.is_empty() {
+                return Ok(out);
+            }
+            out.extend_from_slice(buf);
+            let n = buf.len();
+            r.consume(n);
+        }
+    }
+
+    #[test]
+    fn truncated_stream_is_unexpected_eof() {
+        let data = sample(300_000);
+        for &(format, threads) in &COMPRESSED {
+            let file = compress(&data, format);
+            // Before the trailer or end mark, and in the middle.
+            for &cut in &[file.len() - 1, file.len() / 2] {
+                let truncated = Cursor::new(file[..cut].to_vec());
+                let mut options = ReadOptions::new();
+                options.threads(threads);
+
+                let e = decode(&file[..cut], format, &options).unwrap_err();
+                assert_eq!(
+                    e.kind(),
+                    io::ErrorKind::UnexpectedEof,
+                    "{} at {}",
+                    format,
+                    cut
+                );
+
+                let mut r = options.from_reader(truncated, format).unwrap();
+                let e = read_buffered(&mut r).unwrap_err();
+                assert_eq!(
+                    e.kind(),
+                    io::ErrorKind::UnexpectedEof,
+                    "{} at {}",
+                    format,
+                    cut
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn allow_truncated_returns_prefix() {
+        let data = sample(300_000);
+        for &(format, threads) in &COMPRESSED {
+            let file = compress(&data, format);
+            let mut options = ReadOptions::new();
+            options.threads(threads).allow_truncated(true);
+
+            for &cut in &[file.len() - 1, file.len() / 2] {
+                let truncated = Cursor::new(file[..cut].to_vec());
+                let mut r = options.from_reader(truncated, format).unwrap();
+                let mut out = Vec::new();
+                r.read_to_end(&mut out).unwrap();
+                assert!(data.starts_with(&out), "{} at {}", format, cut);
+
+                let t = r.truncation().unwrap();
+                assert_eq!(t.decompressed, out.len() as u64, "{} at {}", format, cut);
+                assert_eq!(t.compressed, cut as u64, "{} at {}", format, cut);
+                // Reading after truncation is end of file.
+                assert_eq!(r.read(&mut [0; 10]).unwrap(), 0);
+            }
+
+            let mut r = options.from_reader(Cursor::new(file), format).unwrap();
+            assert!(read_buffered(&mut r).unwrap() == data, "{}", format);
+            assert_eq!(r.truncation(), None);
+        }
+    }
+
+    #[test]
+    fn concatenated_members() {
+        let data = sample(300_000);
+        for &format in &CONCATENATED {
+            // Like `cat a.gz b.gz`.
+            let mut file = compress(&data[..1000], format);
+            file.extend_from_slice(&compress(&data[1000..], format));
+
+            let all = decode(&file, format, &ReadOptions::new()).unwrap();
+            assert!(all == data, "{}", format);
+            let first = decode(&file, format, ReadOptions::new().multi_member(false)).unwrap();
+            assert!(first == data[..1000], "{}", format);
+        }
+    }
+
+    #[test]
+    fn concatenated_gzip_with_threads() {
+        let data = sample(300_000);
+        for &format in &[Format::Gzip, Format::Bgzf] {
+            let mut file = compress(&data[..100_000], format);
+            file.extend_from_slice(&compress(&data[100_000..], format));
+
+            let all = decode(&file, format, ReadOptions::new().threads(3)).unwrap();
+            assert!(all == data, "{}", format);
+        }
     }
 }
@@ -0,0 +1,674 @@
+//! LZ4 frame parameters and decoding.
+
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use ::lz4::liblz4::{BlockChecksum, BlockMode, BlockSize, ContentChecksum};
+use ::lz4::{Decoder, EncoderBuilder};
+
+use crate::seek::Chunk;
+use crate::{read_header, Error};
+
+const MAGIC: u32 = 0x184d_2204;
+
+// Frame flags.
+const FLAG_DICT_ID: u8 = 0x01;
+const FLAG_CONTENT_CHECKSUM: u8 = 0x04;
+const FLAG_CONTENT_SIZE: u8 = 0x08;
+const FLAG_BLOCK_CHECKSUM: u8 = 0x10;
+const FLAG_BLOCK_INDEPENDENCE: u8 = 0x20;
+
+/// Block size word has this bit if the block is stored uncompressed.
+const UNCOMPRESSED_BLOCK: u32 = 0x8000_0000;
+
+/// Frame parameters of LZ4 output.
+///
+/// ```no_run
+/// use detect_compression::{Level, Lz4BlockMode, Lz4BlockSize, Lz4Options, WriteOptions};
+///
+/// let mut lz4 = Lz4Options::new();
+/// lz4.block_size(Lz4BlockSize::Max4MB)
+///     .block_mode(Lz4BlockMode::Independent)
+///     .block_checksum(true);
+///
+/// let writer = WriteOptions::new()
+///     .lz4_options(lz4)
+///     .create("out.lz4", Level::Default)?;
+/// writer.finalize()?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lz4Options {
+    block_size: Lz4BlockSize,
+    block_mode: Lz4BlockMode,
+    block_checksum: bool,
+    content_checksum: bool,
+    favor_dec_speed: bool,
+    content_size: Option<u64>,
+}
+
+impl Lz4Options {
+    /// Create default options.
+    ///
+    /// Default is 64 KiB linked blocks without block checksums, with content checksum.
+    pub fn new() -> Lz4Options {
+        Lz4Options {
+            block_size: Lz4BlockSize::Max64KB,
+            block_mode: Lz4BlockMode::Linked,
+            block_checksum: false,
+            content_checksum: true,
+            favor_dec_speed: false,
+            content_size: None,
+        }
+    }
+
+    /// Set maximum size of uncompressed data in a block.
+    pub fn block_size(&mut self, block_size: Lz4BlockSize) -> &mut Lz4Options {
+        self.block_size = block_size;
+        self
+    }
+
+    /// Set whether blocks refer to previous blocks.
+    pub fn block_mode(&mut self, block_mode: Lz4BlockMode) -> &mut Lz4Options {
+        self.block_mode = block_mode;
+        self
+    }
+
+    /// Add checksum to each block.
+    pub fn block_checksum(&mut self, block_checksum: bool) -> &mut Lz4Options {
+        self.block_checksum = block_checksum;
+        self
+    }
+
+    /// Add checksum of whole uncompressed content at end of frame.
+    pub fn content_checksum(&mut self, content_checksum: bool) -> &mut Lz4Options {
+        self.content_checksum = content_checksum;
+        self
+    }
+
+    /// Favor decompression speed over compression ratio in LZ4 HC levels.
+    pub fn favor_dec_speed(&mut self, favor_dec_speed: bool) -> &mut Lz4Options {
+        self.favor_dec_speed = favor_dec_speed;
+        self
+    }
+
+    /// Write uncompressed content size to frame header.
+    ///
+    /// Total bytes written to the writer must be exactly this size.
+    pub fn content_size(&mut self, content_size: Option<u64>) -> &mut Lz4Options {
+        self.content_size = content_size;
+        self
+    }
+
+    pub(crate) fn apply(&self, builder: &mut EncoderBuilder) {
+        builder
+            .block_size(self.block_size.into_lz4())
+            .block_mode(self.block_mode.into_lz4())
+            .block_checksum(if self.block_checksum {
+                BlockChecksum::BlockChecksumEnabled
+            } else {
+                BlockChecksum::NoBlockChecksum
+            })
+            .checksum(if self.content_checksum {
+                ContentChecksum::ChecksumEnabled
+            } else {
+                ContentChecksum::NoChecksum
+            })
+            .favor_dec_speed(self.favor_dec_speed);
+        if let Some(content_size) = self.content_size {
+            builder.content_size(content_size);
+        }
+    }
+}
+
+impl Default for Lz4Options {
+    fn default() -> Lz4Options {
+        Lz4Options::new()
+    }
+}
+
+/// Maximum size of uncompressed data in a LZ4 block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lz4BlockSize {
+    /// 64 KiB
+    Max64KB,
+    /// 256 KiB
+    Max256KB,
+    /// 1 MiB
+    Max1MB,
+    /// 4 MiB
+    Max4MB,
+}
+
+impl Lz4BlockSize {
+    fn into_lz4(self) -> BlockSize {
+        match self {
+            Lz4BlockSize::Max64KB => BlockSize::Max64KB,
+            Lz4BlockSize::Max256KB => BlockSize::Max256KB,
+            Lz4BlockSize::Max1MB => BlockSize::Max1MB,
+            Lz4BlockSize::Max4MB => BlockSize::Max4MB,
+        }
+    }
+}
+
+/// Dependency between LZ4 blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lz4BlockMode {
+    /// Blocks refer to previous blocks. Better compression.
+    Linked,
+    /// Blocks are compressed independently. Each block can be decoded alone.
+    Independent,
+}
+
+impl Lz4BlockMode {
+    fn into_lz4(self) -> BlockMode {
+        match self {
+            Lz4BlockMode::Linked => BlockMode::Linked,
+            Lz4BlockMode::Independent => BlockMode::Independent,
+        }
+    }
+}
+
+/// LZ4 decoder reports a frame without its end mark as an error, and decodes following frames.
+///
+/// `lz4::Decoder` returns end of file when the underlying stream ends, even in the middle of the frame.
+/// It also stops at end of the first frame.
+pub(crate) struct Lz4Decoder<R> {
+    /// `None` after end of the last frame.
+    inner: Option<Decoder<FrameReader<R>>>,
+    multi_frame: bool,
+}
+
+impl<R: Read> Lz4Decoder<R> {
+    pub(crate) fn new(r: R, multi_frame: bool) -> io::Result<Lz4Decoder<R>> {
+        Ok(Lz4Decoder {
+            inner: Some(Decoder::new(FrameReader::new(r))?),
+            multi_frame,
+        })
+    }
+}
+
+impl<R: Read> Read for Lz4Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = match self.inner {
+                Some(ref mut d) => d.read(buf)?,
+                None => return Ok(0),
+            };
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+
+            // `FrameReader` ends at the end of the frame, so nothing is lost on finish.
+            let d = self.inner.take().expect("checked to be decoding");
+            let (mut r, res) = d.finish();
+            if res.is_err() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "LZ4 frame ends before its end mark",
+                ));
+            }
+            if !self.multi_frame || !r.has_more()? {
+                return Ok(0);
+            }
+            self.inner = Some(Decoder::new(r)?);
+        }
+    }
+}
+
+/// Stream of one frame at a time, with a byte read ahead to find whether another frame follows.
+///
+/// `lz4::Decoder` may read beyond the end of the frame, and the bytes it has read are lost on `finish()`.
+/// This follows the frame layout, and ends at the end of the frame.
+struct FrameReader<R> {
+    inner: R,
+    byte: Option<u8>,
+    field: Field,
+    /// Bytes left in the current field.
+    left: u64,
+    /// Last 4 bytes of the current field, to parse it.
+    head: [u8; 4],
+    flags: u8,
+}
+
+/// Part of a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Magic,
+    SkippableSize,
+    SkippableData,
+    Descriptor,
+    /// Content size, dictionary ID and header checksum.
+    HeaderRest,
+    BlockSize,
+    /// Block data and its checksum.
+    BlockData,
+    ContentChecksum,
+    /// Not a frame. The decoder reports the error.
+    Unknown,
+    End,
+}
+
+impl<R: Read> FrameReader<R> {
+    fn new(inner: R) -> FrameReader<R> {
+        FrameReader {
+            inner,
+            byte: None,
+            field: Field::Magic,
+            left: 4,
+            head: [0; 4],
+            flags: 0,
+        }
+    }
+
+    /// Find whether another frame follows, and start reading it.
+    fn has_more(&mut self) -> io::Result<bool> {
+        let mut byte = [0u8];
+        loop {
+            match self.inner.read(&mut byte) {
+                Ok(0) => return Ok(false),
+                Ok(_) => {
+                    self.byte = Some(byte[0]);
+                    self.start(Field::Magic, 4);
+                    return Ok(true);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn start(&mut self, field: Field, len: u64) {
+        self.field = field;
+        self.left = len;
+        if len == 0 && field != Field::Unknown && field != Field::End {
+            self.next_field();
+        }
+    }
+
+    /// Start the field following the current one.
+    fn next_field(&mut self) {
+        let value = u32::from_le_bytes(self.head);
+        match self.field {
+            Field::Magic if value & 0xffff_fff0 == 0x184d_2a50 => {
+                self.start(Field::SkippableSize, 4)
+            }
+            Field::Magic if value == MAGIC => self.start(Field::Descriptor, 2),
+            Field::Magic => self.start(Field::Unknown, 0),
+            Field::SkippableSize => self.start(Field::SkippableData, u64::from(value)),
+            Field::Descriptor => {
+                self.flags = self.head[2];
+                let mut rest = 1;
+                if self.flags & FLAG_CONTENT_SIZE != 0 {
+                    rest += 8;
+                }
+                if self.flags & FLAG_DICT_ID != 0 {
+                    rest += 4;
+                }
+                self.start(Field::HeaderRest, rest);
+            }
+            Field::HeaderRest | Field::BlockData => self.start(Field::BlockSize, 4),
+            Field::BlockSize if value == 0 => {
+                let len = if self.flags & FLAG_CONTENT_CHECKSUM != 0 {
+                    4
+                } else {
+                    0
+                };
+                self.start(Field::ContentChecksum, len);
+            }
+            Field::BlockSize => {
+                let mut len = u64::from(value & !UNCOMPRESSED_BLOCK);
+                if self.flags & FLAG_BLOCK_CHECKSUM != 0 {
+                    len += 4;
+                }
+                self.start(Field::BlockData, len);
+            }
+            Field::SkippableData | Field::ContentChecksum => self.start(Field::End, 0),
+            Field::Unknown | Field::End => {}
+        }
+    }
+}
+
+impl<R: Read> Read for FrameReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = match self.field {
+            Field::End => return Ok(0),
+            Field::Unknown => buf.len(),
+            _ => buf
+                .len()
+                .min(usize::try_from(self.left).unwrap_or(usize::MAX)),
+        };
+        let n = match self.byte {
+            Some(byte) if len > 0 => {
+                buf[0] = byte;
+                self.byte = None;
+                1
+            }
+            _ => self.inner.read(&mut buf[..len])?,
+        };
+        if self.field == Field::Unknown {
+            return Ok(n);
+        }
+
+        // Fields shorter than 4 bytes end at the end of `head`.
+        let pos = 4 - self.left.min(4) as usize;
+        for (h, b) in self.head[pos..].iter_mut().zip(&buf[..n]) {
+            *h = *b;
+        }
+        self.left -= n as u64;
+        if self.left == 0 {
+            self.next_field();
+        }
+        Ok(n)
+    }
+}
+
+/// Find blocks of frames, each of which is a chunk.
+///
+/// Blocks must be independent. Uncompressed size of a block is found from its sequences, without decoding.
+pub(crate) fn scan_blocks<R: Read + Seek>(r: &mut R) -> io::Result<Vec<Chunk>> {
+    let mut chunks = Vec::new();
+    let mut compressed = r.seek(SeekFrom::Start(0))?;
+    let mut uncompressed = 0;
+
+    loop {
+        let mut magic = [0u8; 4];
+        match read_header(r, &mut magic)? {
+            // End of file between frames.
+            0 => break,
+            4 => {}
+            _ => return Err(unexpected_eof()),
+        }
+        let magic = u32::from_le_bytes(magic);
+        compressed += 4;
+
+        // Skippable frame.
+        if magic & 0xffff_fff0 == 0x184d_2a50 {
+            let size = read_u32(r)?;
+            r.seek(SeekFrom::Current(i64::from(size)))?;
+            compressed += 4 + u64::from(size);
+            continue;
+        }
+        if magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid LZ4 frame magic",
+            ));
+        }
+
+        let mut descriptor = [0u8; 2];
+        r.read_exact(&mut descriptor)?;
+        let flags = descriptor[0];
+        if flags & FLAG_BLOCK_INDEPENDENCE == 0 {
+            return Err(
+                Error::invalid_input("LZ4 frame with linked blocks is not seekable").into(),
+            );
+        }
+        // Content size, dictionary ID and header checksum.
+        let mut rest = 1;
+        if flags & FLAG_CONTENT_SIZE != 0 {
+            rest += 8;
+        }
+        if flags & FLAG_DICT_ID != 0 {
+            rest += 4;
+        }
+        r.seek(SeekFrom::Current(rest))?;
+        compressed += 2 + rest as u64;
+
+        loop {
+            let size = read_u32(r)?;
+            if size == 0 {
+                compressed += 4;
+                break;
+            }
+            let len = size & !UNCOMPRESSED_BLOCK;
+
+            let mut block = Vec::new();
+            r.by_ref().take(u64::from(len)).read_to_end(&mut block)?;
+            if block.len() < len as usize {
+                return Err(unexpected_eof());
+            }
+            let block_len = if size & UNCOMPRESSED_BLOCK != 0 {
+                u64::from(len)
+            } else {
+                block_len(&block)?
+            };
+
+            chunks.push(Chunk {
+                compressed,
+                uncompressed,
+            });
+            compressed += 4 + u64::from(len);
+            uncompressed += block_len;
+
+            if flags & FLAG_BLOCK_CHECKSUM != 0 {
+                r.seek(SeekFrom::Current(4))?;
+                compressed += 4;
+            }
+        }
+        if flags & FLAG_CONTENT_CHECKSUM != 0 {
+            r.seek(SeekFrom::Current(4))?;
+            compressed += 4;
+        }
+    }
+
+    // Seeking beyond end of file succeeds.
+    if compressed > r.seek(SeekFrom::End(0))? {
+        return Err(unexpected_eof());
+    }
+    chunks.push(Chunk {
+        compressed,
+        uncompressed,
+    });
+    Ok(chunks)
+}
+
+/// Decode a block, starting with its size, into `len` bytes.
+pub(crate) fn decode_block(raw: &[u8], len: usize) -> io::Result<Vec<u8>> {
+    let size = raw.get(..4).ok_or_else(unexpected_eof)?;
+    let size = u32::from_le_bytes([size[0], size[1], size[2], size[3]]);
+    let data = raw
+        .get(4..4 + (size & !UNCOMPRESSED_BLOCK) as usize)
+        .ok_or_else(unexpected_eof)?;
+
+    if size & UNCOMPRESSED_BLOCK != 0 {
+        Ok(data.to_vec())
+    } else {
+        ::lz4::block::decompress(data, Some(len as i32))
+    }
+}
+
+/// Uncompressed size of a block, sum of literals and matches of its sequences.
+fn block_len(block: &[u8]) -> io::Result<u64> {
+    let mut pos = 0;
+    let mut len = 0;
+    loop {
+        let token = *block.get(pos).ok_or_else(corrupt_block)?;
+        pos += 1;
+
+        let literals = sequence_len(block, &mut pos, token >> 4)?;
+        pos += literals;
+        len += literals as u64;
+        // The last sequence has only literals.
+        match pos.cmp(&block.len()) {
+            Ordering::Equal => return Ok(len),
+            Ordering::Greater => return Err(corrupt_block()),
+            Ordering::Less => {}
+        }
+
+        // Match offset.
+        pos += 2;
+        len += sequence_len(block, &mut pos, token & 0x0f)? as u64 + 4;
+    }
+}
+
+/// Read length of literals or match, continued by bytes while the value is the max.
+fn sequence_len(block: &[u8], pos: &mut usize, nibble: u8) -> io::Result<usize> {
+    let mut len = usize::from(nibble);
+    if nibble == 0x0f {
+        loop {
+            let b = *block.get(*pos).ok_or_else(corrupt_block)?;
+            *pos += 1;
+            len += usize::from(b);
+            if b != 0xff {
+                break;
+            }
+        }
+    }
+    Ok(len)
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn unexpected_eof() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "LZ4 frame ends before its end mark",
+    )
+}
+
+fn corrupt_block() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "corrupt LZ4 block")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+
+    use ::lz4::liblz4::{BlockChecksum, BlockMode, BlockSize, ContentChecksum};
+    use ::lz4::EncoderBuilder;
+
+    use super::{block_len, Lz4Decoder};
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| (i * 7 % 251) as u8 ^ (i / 1000) as u8)
+            .collect()
+    }
+
+    /// Random text, which compresses to 3/4 like base64.
+    fn base64(len: usize) -> Vec<u8> {
+        let chars = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut x = 0x2545_f491_4f6c_dd1d_u64;
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                chars[(x % 64) as usize]
+            })
+            .collect()
+    }
+
+    fn frame(data: &[u8]) -> Vec<u8> {
+        frame_with(data, &EncoderBuilder::new())
+    }
+
+    fn frame_with(data: &[u8], builder: &EncoderBuilder) -> Vec<u8> {
+        let mut e = builder.build(Vec::new()).unwrap();
+        e.write_all(data).unwrap();
+        let (out, res) = e.finish();
+        res.unwrap();
+        out
+    }
+
+    fn decode(file: &[u8], multi_frame: bool) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        Lz4Decoder::new(file, multi_frame)?.read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn concatenated_frames() {
+        let data = sample(300_000);
+        // Like `cat a.lz4 b.lz4 c.lz4`, with an empty frame in the middle.
+        let mut file = frame(&data[..100_000]);
+        file.extend_from_slice(&frame(&[]));
+        file.extend_from_slice(&frame(&data[100_000..]));
+
+        assert_eq!(decode(&file, true).unwrap(), data);
+        assert_eq!(decode(&file, false).unwrap(), &data[..100_000]);
+    }
+
+    #[test]
+    fn frame_layouts() {
+        // Two frames of the same length, for the content size.
+        let data = base64(300_000);
+        let mut builders = Vec::new();
+        // Default of the lz4 command.
+        let mut b = EncoderBuilder::new();
+        b.block_size(BlockSize::Max4MB)
+            .block_mode(BlockMode::Independent)
+            .checksum(ContentChecksum::ChecksumEnabled);
+        builders.push(b);
+        let mut b = EncoderBuilder::new();
+        b.block_size(BlockSize::Max64KB)
+            .block_checksum(BlockChecksum::BlockChecksumEnabled)
+            .checksum(ContentChecksum::NoChecksum)
+            .content_size(150_000);
+        builders.push(b);
+
+        let skippable = [0x50, 0x2a, 0x4d, 0x18, 3, 0, 0, 0, 1, 2, 3];
+        for b in &builders {
+            let mut file = frame_with(&data[..150_000], b);
+            file.extend_from_slice(&skippable);
+            file.extend_from_slice(&frame_with(&data[150_000..], b));
+            assert_eq!(decode(&file, true).unwrap(), data);
+
+            // The decoder must not read past the end of the frame.
+            let mut file = frame_with(&data[..150_000], b);
+            file.extend_from_slice(b"garbage");
+            assert_eq!(decode(&file, false).unwrap(), &data[..150_000]);
+            let e = decode(&file, true).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::Other);
+        }
+    }
+
+    #[test]
+    fn frame_without_end_mark() {
+        let file = frame(&sample(100_000));
+        let e = decode(&file[..file.len() - 8], true).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn empty_frame() {
+        assert!(decode(&frame(&[]), true).unwrap().is_empty());
+    }
+
+    #[test]
+    fn block_len_of_compressed_blocks() {
+        let text: Vec<u8> = (0..100_000u32)
+            .flat_map(|i| format!("line {}\n", i % 1000).into_bytes())
+            .collect();
+        let mut x = 0x2545_f491_4f6c_dd1d_u64;
+        let random: Vec<u8> = (0..70_000)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect();
+        let runs = vec![0u8; 100_000];
+
+        for data in &[&text[..], &random[..], &runs[..], b"a", b""] {
+            let block = ::lz4::block::compress(data, None, false).unwrap();
+            assert_eq!(block_len(&block).unwrap(), data.len() as u64);
+        }
+    }
+
+    #[test]
+    fn block_len_of_corrupt_block() {
+        // Literals beyond the end.
+        assert!(block_len(&[0x50, b'a']).is_err());
+        // Match length continues beyond the end.
+        assert!(block_len(&[0x1f, b'a', 1, 0]).is_err());
+        assert!(block_len(&[]).is_err());
+    }
+}
@@ -0,0 +1,220 @@
+//! Parallel gzip compression like `pigz`.
+
+use std::io::{self, Write};
+use std::mem;
+
+use flate2::{Compress, Compression, Crc, FlushCompress};
+
+use crate::pool::OrderedPool;
+
+/// Final empty block with fixed Huffman codes.
+const FINAL_BLOCK: [u8; 2] = [0x03, 0x00];
+
+/// Gzip encoder compresses blocks in parallel.
+///
+/// Each block is compressed independently and ends with a sync flush,
+/// so that the compressed blocks concatenate into a single deflate stream.
+/// Back references don't cross blocks, so compression ratio is slightly worse than single-threaded one.
+pub(crate) struct ParGzEncoder<W: Write> {
+    inner: W,
+    level: Compression,
+    block_size: usize,
+    buf: Vec<u8>,
+    pool: OrderedPool<io::Result<Block>>,
+    crc: Crc,
+}
+
+struct Block {
+    data: Vec<u8>,
+    crc: Crc,
+}
+
+impl<W: Write> ParGzEncoder<W> {
+    /// `header` is encoded gzip header.
+    pub(crate) fn new(
+        mut inner: W,
+        header: &[u8],
+        level: Compression,
+        threads: usize,
+        block_size: usize,
+    ) -> io::Result<ParGzEncoder<W>> {
+        inner.write_all(header)?;
+
+        let block_size = block_size.max(1);
+        Ok(ParGzEncoder {
+            inner,
+            level,
+            block_size,
+            buf: Vec::with_capacity(block_size),
+            pool: OrderedPool::new(threads),
+            crc: Crc::new(),
+        })
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Compress all buffered data, and write the end of the stream.
+    pub(crate) fn try_finish(&mut self) -> io::Result<()> {
+        self.write_all_blocks()?;
+
+        self.inner.write_all(&FINAL_BLOCK)?;
+        self.inner.write_all(&self.crc.sum().to_le_bytes())?;
+        self.inner.write_all(&self.crc.amount().to_le_bytes())?;
+        Ok(())
+    }
+
+    fn submit(&mut self) -> io::Result<()> {
+        while self.pool.is_full() {
+            self.write_next()?;
+        }
+
+        let data = mem::replace(&mut self.buf, Vec::with_capacity(self.block_size));
+        let level = self.level;
+        self.pool.submit(move || compress(&data, level))
+    }
+
+    fn write_next(&mut self) -> io::Result<()> {
+        if let Some(res) = self.pool.next() {
+            let block = res??;
+            self.crc.combine(&block.crc);
+            self.inner.write_all(&block.data)?;
+        }
+        Ok(())
+    }
+
+    fn write_all_blocks(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            self.submit()?;
+        }
+        while !self.pool.is_empty() {
+            self.write_next()?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ParGzEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.block_size - self.buf.len());
+        self.buf.extend_from_slice(&buf[..n]);
+
+        if self.buf.len() == self.block_size {
+            self.submit()?;
+        }
+        Ok(n)
+    }
+
+    /// Flush ends current block, like `Z_SYNC_FLUSH`.
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_all_blocks()?;
+        self.inner.flush()
+    }
+}
+
+/// Compress a block into deflate blocks ending with sync flush.
+fn compress(data: &[u8], level: Compression) -> io::Result<Block> {
+    let mut c = Compress::new(level, false);
+    let mut out = Vec::with_capacity(data.len() + data.len() / 16 + 64);
+
+    loop {
+        let pos = c.total_in() as usize;
+        c.compress_vec(&data[pos..], &mut out, FlushCompress::Sync)?;
+
+        // Flush is complete when output space remains.
+        if c.total_in() as usize == data.len() && out.len() < out.capacity() {
+            break;
+        }
+        out.reserve(out.capacity());
+    }
+
+    let mut crc = Crc::new();
+    crc.update(data);
+    Ok(Block { data: out, crc })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use flate2::read::{GzDecoder, MultiGzDecoder};
+    use flate2::Compression;
+
+    use super::ParGzEncoder;
+    use crate::GzipHeader;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| (i * 7 % 251) as u8 ^ (i / 1000) as u8)
+            .collect()
+    }
+
+    /// Write `data` by `chunk` bytes, flushing after each write if `flush`.
+    fn compress(
+        data: &[u8],
+        level: Compression,
+        block_size: usize,
+        chunk: usize,
+        flush: bool,
+    ) -> Vec<u8> {
+        let header = GzipHeader::new().to_bytes(level).unwrap();
+        let mut e = ParGzEncoder::new(Vec::new(), &header, level, 3, block_size).unwrap();
+        for c in data.chunks(chunk) {
+            e.write_all(c).unwrap();
+            if flush {
+                e.flush().unwrap();
+            }
+        }
+        e.try_finish().unwrap();
+        e.get_mut().clone()
+    }
+
+    /// Decode checking CRC and size, and check the output is a single member.
+    fn assert_round_trip(gz: &[u8], data: &[u8]) {
+        let mut multi = Vec::new();
+        MultiGzDecoder::new(gz).read_to_end(&mut multi).unwrap();
+        assert_eq!(multi, data);
+
+        let mut single = Vec::new();
+        GzDecoder::new(gz).read_to_end(&mut single).unwrap();
+        assert_eq!(single, data);
+    }
+
+    #[test]
+    fn block_size_one() {
+        let data = sample(3000);
+        let gz = compress(&data, Compression::default(), 1, 100, false);
+        assert_round_trip(&gz, &data);
+    }
+
+    #[test]
+    fn exact_multiple_of_block_size() {
+        let data = sample(8 * 1024);
+        for &chunk in &[1024, 1000, 8 * 1024] {
+            let gz = compress(&data, Compression::default(), 1024, chunk, false);
+            assert_round_trip(&gz, &data);
+        }
+    }
+
+    #[test]
+    fn empty_input() {
+        let gz = compress(&[], Compression::default(), 1024, 1, false);
+        assert_round_trip(&gz, &[]);
+    }
+
+    #[test]
+    fn level_none() {
+        let data = sample(300 * 1024);
+        let gz = compress(&data, Compression::none(), 64 * 1024, 10_000, false);
+        assert!(gz.len() > data.len());
+        assert_round_trip(&gz, &data);
+    }
+
+    #[test]
+    fn flush_between_writes() {
+        let data = sample(100 * 1024);
+        let gz = compress(&data, Compression::default(), 16 * 1024, 3000, true);
+        assert_round_trip(&gz, &data);
+    }
+}
@@ -0,0 +1,151 @@
+//! Thread pool returning results in submission order.
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Number of threads to use. `0` means the number of available CPUs.
+pub(crate) fn thread_count(threads: usize) -> usize {
+    match threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }
+}
+
+/// Runs jobs on worker threads, and returns their results in order of submission.
+pub(crate) struct OrderedPool<T> {
+    jobs: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+    pending: VecDeque<Receiver<T>>,
+    max_pending: usize,
+}
+
+impl<T: 'static + Send> OrderedPool<T> {
+    /// Start `threads` workers.
+    ///
+    /// Up to twice as many jobs as workers are in flight.
+    pub(crate) fn new(threads: usize) -> OrderedPool<T> {
+        let threads = threads.max(1);
+        let (tx, rx) = mpsc::channel::<Job>();
+        let rx = Arc::new(Mutex::new(rx));
+
+        let workers = (0..threads)
+            .map(|_| {
+                let rx = rx.clone();
+                thread::spawn(move || loop {
+                    // Lock only while receiving, so that jobs run in parallel.
+                    let job = match rx.lock() {
+                        Ok(rx) => rx.recv(),
+                        Err(_) => return,
+                    };
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => return,
+                    }
+                })
+            })
+            .collect();
+
+        OrderedPool {
+            jobs: Some(tx),
+            workers,
+            pending: VecDeque::new(),
+            max_pending: 2 * threads,
+        }
+    }
+
+    /// Whether results should be taken before submitting more jobs.
+    pub(crate) fn is_full(&self) -> bool {
+        self.pending.len() >= self.max_pending
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub(crate) fn submit<F>(&mut self, f: F) -> io::Result<()>
+    where
+        F: 'static + Send + FnOnce() -> T,
+    {
+        let (tx, rx) = mpsc::channel();
+        let job: Job = Box::new(move || {
+            // The receiver is gone if the pool is dropped.
+            let _ = tx.send(f());
+        });
+
+        let jobs = self.jobs.as_ref().expect("jobs sender lives until drop");
+        jobs.send(job).map_err(|_| worker_lost())?;
+        self.pending.push_back(rx);
+        Ok(())
+    }
+
+    /// Wait for the result of the oldest job.
+    ///
+    /// Returns `None` if no job is pending.
+    pub(crate) fn next(&mut self) -> Option<io::Result<T>> {
+        let rx = self.pending.pop_front()?;
+        Some(rx.recv().map_err(|_| worker_lost()))
+    }
+}
+
+impl<T> Drop for OrderedPool<T> {
+    fn drop(&mut self) {
+        // Workers exit when the queue is closed.
+        self.jobs = None;
+        for w in self.workers.drain(..) {
+            let _ = w.join();
+        }
+    }
+}
+
+/// The job panicked.
+fn worker_lost() -> io::Error {
+    io::Error::other("worker thread panicked")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::OrderedPool;
+
+    #[test]
+    fn results_in_submission_order() {
+        let mut pool = OrderedPool::new(4);
+        let mut results = Vec::new();
+        for i in 0..20u64 {
+            while pool.is_full() {
+                results.push(pool.next().unwrap().unwrap());
+            }
+            // Earlier jobs finish later.
+            pool.submit(move || {
+                thread::sleep(Duration::from_millis((20 - i) % 5));
+                i
+            })
+            .unwrap();
+        }
+        while let Some(res) = pool.next() {
+            results.push(res.unwrap());
+        }
+
+        assert!(pool.is_empty());
+        assert_eq!(results, (0..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn panicked_job_is_error() {
+        let mut pool = OrderedPool::new(2);
+        pool.submit(|| 1).unwrap();
+        pool.submit(|| -> i32 { panic!("job failed") }).unwrap();
+        pool.submit(|| 3).unwrap();
+
+        assert_eq!(pool.next().unwrap().unwrap(), 1);
+        assert!(pool.next().unwrap().is_err());
+        assert_eq!(pool.next().unwrap().unwrap(), 3);
+    }
+}
@@ -0,0 +1,572 @@
+//! Random access to compressed files by decoding only the chunks containing requested data.
+//!
+//! Chunks are found from an index: `.gzi` or block headers of BGZF, seek table of zstd seekable format,
+//! or blocks of LZ4 frames.
+
+use std::fs::File;
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::bgzf;
+use crate::error::SourceReader;
+use crate::lz4;
+use crate::{read_error, Error, Format, Result};
+
+/// Size of chunks uncompressed files are read by.
+const PLAIN_CHUNK_LEN: u64 = 1024 * 1024;
+
+// Zstd seekable format.
+const ZSTD_SKIPPABLE_MAGIC: u32 = 0x184d_2a5e;
+const ZSTD_SEEKABLE_MAGIC: u32 = 0x8f92_eab1;
+const ZSTD_SEEK_TABLE_FOOTER_LEN: u64 = 9;
+const ZSTD_CHECKSUM_FLAG: u8 = 0x80;
+const ZSTD_RESERVED_BITS: u8 = 0x7c;
+
+/// Start of a unit of compressed data decodable independently.
+///
+/// The index has the end of the last chunk as an extra entry.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Chunk {
+    /// Offset in the file.
+    pub(crate) compressed: u64,
+    /// Offset in the uncompressed data.
+    pub(crate) uncompressed: u64,
+}
+
+trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// The [`BufRead`](https://doc.rust-lang.org/std/io/trait.BufRead.html) and [`Seek`](https://doc.rust-lang.org/std/io/trait.Seek.html) type
+/// reads compressed file from any position.
+///
+/// Only the chunk containing the position is decoded, so seeking into the middle of a huge file is cheap.
+/// The format must have independently decodable chunks:
+///
+/// | Format | Chunks | Index |
+/// |--------|--------|-------|
+/// | BGZF, Gzip | BGZF blocks | `.gzi` file next to the file if exists, otherwise block headers |
+/// | Zstandard | frames | seek table of the [seekable format](https://github.com/facebook/zstd/blob/dev/contrib/seekable_format/zstd_seekable_compression_format.md) |
+/// | LZ4 | independent blocks | block headers, read through once on open |
+/// | Uncompressed | 1 MiB | none |
+///
+/// Other formats and files without the structure are rejected with [`ErrorKind::InvalidInput`](enum.ErrorKind.html#variant.InvalidInput).
+///
+/// ```no_run
+/// use std::io::{BufRead, Seek, SeekFrom};
+///
+/// use detect_compression::SeekableDetectReader;
+///
+/// let mut r = SeekableDetectReader::open("huge.log.bgz")?;
+/// r.seek(SeekFrom::End(-4096))?;
+/// for line in r.lines() {
+///     println!("{}", line?);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct SeekableDetectReader {
+    inner: SourceReader<Box<dyn ReadSeek>>,
+    format: Format,
+    path: Option<PathBuf>,
+    index: Vec<Chunk>,
+    pos: u64,
+    /// Decoded chunk and its number.
+    cache: Option<(usize, Vec<u8>)>,
+}
+
+impl SeekableDetectReader {
+    /// Open compressed or uncompressed file.
+    ///
+    /// The format is detected from file name extension.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SeekableDetectReader> {
+        let path = path.as_ref();
+        let f = File::open(path).map_err(|e| Error::from(e).with_path(path))?;
+        SeekableDetectReader::new(Box::new(f), Format::from_path(path), Some(path))
+    }
+
+    /// Read compressed or uncompressed stream in the specified format.
+    ///
+    /// BGZF blocks are found from their headers, because there is no `.gzi` file.
+    pub fn from_reader<R: 'static + Read + Seek>(
+        reader: R,
+        format: Format,
+    ) -> Result<SeekableDetectReader> {
+        SeekableDetectReader::new(Box::new(reader), format, None)
+    }
+
+    fn new(
+        r: Box<dyn ReadSeek>,
+        format: Format,
+        path: Option<&Path>,
+    ) -> Result<SeekableDetectReader> {
+        let mut inner = SourceReader(r);
+        let index = match format {
+            Format::Plain => plain_chunks(&mut inner),
+            Format::Gzip | Format::Bgzf => match path {
+                Some(path) => bgzf::read_index(&mut inner, &bgzf::gzi_path(path)).and_then(
+                    |index| match index {
+                        Some(index) => Ok(index),
+                        None => bgzf::scan_blocks(&mut inner),
+                    },
+                ),
+                None => bgzf::scan_blocks(&mut inner),
+            },
+            Format::Zstd => zstd_seek_table(&mut inner),
+            Format::Lz4 => lz4::scan_blocks(&mut inner),
+            _ => {
+                return Err(Error::invalid_input(format!(
+                    "random access is not supported in {}",
+                    format
+                ))
+                .with_context(path, Some(format)))
+            }
+        };
+        let index = index.map_err(|e| read_error(e, path, format))?;
+
+        Ok(SeekableDetectReader {
+            inner,
+            format,
+            path: path.map(Path::to_path_buf),
+            index,
+            pos: 0,
+            cache: None,
+        })
+    }
+
+    /// Format of the file detected on open.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Size of the uncompressed data.
+    pub fn uncompressed_len(&self) -> u64 {
+        self.index[self.index.len() - 1].uncompressed
+    }
+
+    /// Number of the chunk containing `pos`, if not at end.
+    fn chunk_at(&self, pos: u64) -> Option<usize> {
+        if pos >= self.uncompressed_len() {
+            return None;
+        }
+        // The last of chunks starting at or before `pos`. Preceding empty chunks are skipped.
+        Some(self.index.partition_point(|c| c.uncompressed <= pos) - 1)
+    }
+
+    fn decode_chunk(&mut self, n: usize) -> io::Result<Vec<u8>> {
+        let (start, end) = (self.index[n], self.index[n + 1]);
+        let len = (end.uncompressed - start.uncompressed) as usize;
+
+        self.inner.seek(SeekFrom::Start(start.compressed))?;
+        let mut raw = Vec::new();
+        (&mut self.inner)
+            .take(end.compressed - start.compressed)
+            .read_to_end(&mut raw)?;
+        if (raw.len() as u64) < end.compressed - start.compressed {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "file ends before the indexed chunk",
+            ));
+        }
+
+        let data = match self.format {
+            Format::Plain => raw,
+            Format::Gzip | Format::Bgzf => bgzf::decode_blocks(&raw)?,
+            Format::Zstd => zstd::bulk::decompress(&raw, len)?,
+            Format::Lz4 => lz4::decode_block(&raw, len)?,
+            _ => unreachable!("index is built only for seekable formats"),
+        };
+        if data.len() != len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "decoded size doesn't match the index",
+            ));
+        }
+        Ok(data)
+    }
+}
+
+impl Read for SeekableDetectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.fill_buf()?;
+        let n = data.len().min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for SeekableDetectReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let n = match self.chunk_at(self.pos) {
+            Some(n) => n,
+            None => return Ok(&[]),
+        };
+
+        if !matches!(self.cache, Some((cached, _)) if cached == n) {
+            let data = self
+                .decode_chunk(n)
+                .map_err(|e| read_error(e, self.path.as_deref(), self.format))?;
+            self.cache = Some((n, data));
+        }
+
+        let start = (self.pos - self.index[n].uncompressed) as usize;
+        match self.cache {
+            Some((_, ref data)) => Ok(&data[start..]),
+            None => unreachable!("chunk is just decoded"),
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt as u64;
+    }
+}
+
+/// Seeking beyond the end is allowed, and reads nothing there.
+impl Seek for SeekableDetectReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.uncompressed_len().checked_add_signed(n),
+            SeekFrom::Current(n) => self.pos.checked_add_signed(n),
+        };
+        self.pos = new_pos.ok_or_else(|| {
+            Error::invalid_input("seek to a negative or overflowing position")
+                .with_context(self.path.as_deref(), Some(self.format))
+        })?;
+        Ok(self.pos)
+    }
+}
+
+fn plain_chunks<R: Seek>(r: &mut R) -> io::Result<Vec<Chunk>> {
+    let len = r.seek(SeekFrom::End(0))?;
+    let chunks = (0..len)
+        .step_by(PLAIN_CHUNK_LEN as usize)
+        .chain(Some(len))
+        .map(|offset| Chunk {
+            compressed: offset,
+            uncompressed: offset,
+        })
+        .collect();
+    Ok(chunks)
+}
+
+/// Read seek table in the skippable frame at the end of the file.
+fn zstd_seek_table<R: Read + Seek>(r: &mut R) -> io::Result<Vec<Chunk>> {
+    let file_len = r.seek(SeekFrom::End(0))?;
+    if file_len < ZSTD_SEEK_TABLE_FOOTER_LEN {
+        return Err(not_zstd_seekable());
+    }
+
+    let mut footer = [0u8; ZSTD_SEEK_TABLE_FOOTER_LEN as usize];
+    r.seek(SeekFrom::Start(file_len - ZSTD_SEEK_TABLE_FOOTER_LEN))?;
+    r.read_exact(&mut footer)?;
+    if u32_at(&footer, 5) != ZSTD_SEEKABLE_MAGIC {
+        return Err(not_zstd_seekable());
+    }
+    let frames = u64::from(u32_at(&footer, 0));
+    let descriptor = footer[4];
+    if descriptor & ZSTD_RESERVED_BITS != 0 {
+        return Err(invalid_seek_table());
+    }
+    let entry_len = if descriptor & ZSTD_CHECKSUM_FLAG != 0 {
+        12
+    } else {
+        8
+    };
+
+    // Skippable frame header, entries, and footer.
+    let table_len = frames * entry_len + ZSTD_SEEK_TABLE_FOOTER_LEN;
+    let data_len = file_len
+        .checked_sub(8 + table_len)
+        .ok_or_else(invalid_seek_table)?;
+    let mut table = Vec::new();
+    r.seek(SeekFrom::Start(data_len))?;
+    r.by_ref().take(8 + table_len).read_to_end(&mut table)?;
+    if u32_at(&table, 0) != ZSTD_SKIPPABLE_MAGIC || u64::from(u32_at(&table, 4)) != table_len {
+        return Err(invalid_seek_table());
+    }
+
+    let mut chunks = Vec::with_capacity(frames as usize + 1);
+    let (mut compressed, mut uncompressed) = (0, 0);
+    for entry in table[8..]
+        .chunks_exact(entry_len as usize)
+        .take(frames as usize)
+    {
+        chunks.push(Chunk {
+            compressed,
+            uncompressed,
+        });
+        compressed += u64::from(u32_at(entry, 0));
+        uncompressed += u64::from(u32_at(entry, 4));
+    }
+    if compressed != data_len {
+        return Err(invalid_seek_table());
+    }
+    chunks.push(Chunk {
+        compressed,
+        uncompressed,
+    });
+    Ok(chunks)
+}
+
+fn u32_at(buf: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]])
+}
+
+fn not_zstd_seekable() -> io::Error {
+    Error::invalid_input("zstd file is not in the seekable format").into()
+}
+
+fn invalid_seek_table() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "zstd seek table doesn't match the file",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, BufRead, Cursor, Read, Seek, SeekFrom, Write};
+
+    use ::lz4::{BlockMode, EncoderBuilder};
+    use flate2::Compression;
+
+    use super::SeekableDetectReader;
+    use crate::bgzf::BgzfWriter;
+    use crate::{ErrorKind, Format};
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| (i * 7 % 251) as u8 ^ (i / 1000) as u8)
+            .collect()
+    }
+
+    fn bgzf(data: &[u8]) -> Vec<u8> {
+        let mut w = BgzfWriter::new(Vec::new(), Compression::default(), 2, None);
+        w.write_all(data).unwrap();
+        w.try_finish().unwrap();
+        w.get_mut().clone()
+    }
+
+    /// LZ4 frame written by `chunk` bytes with flush between writes, so blocks have various sizes.
+    fn lz4(data: &[u8], mode: BlockMode, chunk: usize) -> Vec<u8> {
+        let mut e = EncoderBuilder::new()
+            .block_mode(mode)
+            .block_checksum(::lz4::liblz4::BlockChecksum::BlockChecksumEnabled)
+            .build(Vec::new())
+            .unwrap();
+        for c in data.chunks(chunk) {
+            e.write_all(c).unwrap();
+            e.flush().unwrap();
+        }
+        let (out, res) = e.finish();
+        res.unwrap();
+        out
+    }
+
+    /// Zstd frames of `chunk` bytes with the seek table, and the frame sizes in it.
+    fn zstd_seekable(data: &[u8], chunk: usize) -> (Vec<u8>, Vec<(u32, u32)>) {
+        let mut out = Vec::new();
+        let mut sizes = Vec::new();
+        for c in data.chunks(chunk) {
+            let frame = zstd::bulk::compress(c, 3).unwrap();
+            sizes.push((frame.len() as u32, c.len() as u32));
+            out.extend_from_slice(&frame);
+        }
+        (out, sizes)
+    }
+
+    fn seek_table(sizes: &[(u32, u32)], checksum: bool) -> Vec<u8> {
+        let entry_len = if checksum { 12 } else { 8 };
+        let mut table = Vec::new();
+        table.extend_from_slice(&0x184d_2a5e_u32.to_le_bytes());
+        table.extend_from_slice(&((sizes.len() * entry_len + 9) as u32).to_le_bytes());
+        for &(compressed, decompressed) in sizes {
+            table.extend_from_slice(&compressed.to_le_bytes());
+            table.extend_from_slice(&decompressed.to_le_bytes());
+            if checksum {
+                table.extend_from_slice(&0u32.to_le_bytes());
+            }
+        }
+        table.extend_from_slice(&(sizes.len() as u32).to_le_bytes());
+        table.push(if checksum { 0x80 } else { 0 });
+        table.extend_from_slice(&0x8f92_eab1_u32.to_le_bytes());
+        table
+    }
+
+    /// Returns at most 3 bytes per read.
+    struct ShortReads(Cursor<Vec<u8>>);
+
+    impl Read for ShortReads {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(3);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    impl Seek for ShortReads {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.0.seek(pos)
+        }
+    }
+
+    fn open(file: Vec<u8>, format: Format) -> SeekableDetectReader {
+        SeekableDetectReader::from_reader(Cursor::new(file), format).unwrap()
+    }
+
+    fn read_at(r: &mut SeekableDetectReader, pos: SeekFrom, len: u64) -> Vec<u8> {
+        r.seek(pos).unwrap();
+        let mut out = Vec::new();
+        r.take(len).read_to_end(&mut out).unwrap();
+        out
+    }
+
+    /// Seek around chunk boundaries, to the last byte, from the end, and past the end.
+    fn assert_seeks(mut r: SeekableDetectReader, data: &[u8]) {
+        assert_eq!(r.uncompressed_len(), data.len() as u64);
+
+        let boundaries: Vec<u64> = r.index.iter().map(|c| c.uncompressed).collect();
+        assert!(boundaries.len() > 3);
+        for &b in &boundaries {
+            for pos in b.saturating_sub(1)..=b + 1 {
+                let start = (pos as usize).min(data.len());
+                let end = (start + 100).min(data.len());
+                let out = read_at(&mut r, SeekFrom::Start(pos), 100);
+                assert_eq!(out, &data[start..end], "at {}", pos);
+            }
+        }
+
+        let last = data.len() as u64 - 1;
+        assert_eq!(
+            read_at(&mut r, SeekFrom::Start(last), 10),
+            &data[last as usize..]
+        );
+        assert_eq!(
+            read_at(&mut r, SeekFrom::End(-1), 10),
+            &data[last as usize..]
+        );
+        let n = 70_000;
+        assert_eq!(
+            read_at(&mut r, SeekFrom::End(-n), n as u64),
+            &data[data.len() - n as usize..]
+        );
+        assert_eq!(
+            read_at(&mut r, SeekFrom::Current(-10), 5),
+            &data[data.len() - 10..data.len() - 5]
+        );
+
+        assert_eq!(r.seek(SeekFrom::End(10)).unwrap(), data.len() as u64 + 10);
+        assert!(r.fill_buf().unwrap().is_empty());
+        assert!(read_at(&mut r, SeekFrom::Start(u64::MAX), 10).is_empty());
+        assert!(r.seek(SeekFrom::End(-(data.len() as i64) - 1)).is_err());
+
+        r.seek(SeekFrom::Start(0)).unwrap();
+        let mut all = Vec::new();
+        r.read_to_end(&mut all).unwrap();
+        assert_eq!(all, data);
+    }
+
+    #[test]
+    fn seek_bgzf() {
+        let data = sample(500_000);
+        assert_seeks(open(bgzf(&data), Format::Bgzf), &data);
+    }
+
+    #[test]
+    fn seek_lz4() {
+        let data = sample(500_000);
+        let mut file = lz4(&data[..300_000], BlockMode::Independent, 50_000);
+        file.extend_from_slice(&lz4(&data[300_000..], BlockMode::Independent, 70_000));
+        assert_seeks(open(file, Format::Lz4), &data);
+    }
+
+    #[test]
+    fn seek_with_short_reads() {
+        let data = sample(300_000);
+        let mut lz4_file = lz4(&data[..100_000], BlockMode::Independent, 30_000);
+        lz4_file.extend_from_slice(&lz4(&data[100_000..], BlockMode::Independent, 70_000));
+        let (mut zstd_file, sizes) = zstd_seekable(&data, 60_000);
+        zstd_file.extend_from_slice(&seek_table(&sizes, true));
+
+        for (file, format) in [
+            (lz4_file, Format::Lz4),
+            (zstd_file, Format::Zstd),
+            (bgzf(&data), Format::Bgzf),
+        ] {
+            let r = ShortReads(Cursor::new(file));
+            let r = SeekableDetectReader::from_reader(r, format).unwrap();
+            assert_seeks(r, &data);
+        }
+    }
+
+    #[test]
+    fn seek_zstd() {
+        let data = sample(500_000);
+        for &checksum in &[false, true] {
+            let (mut file, sizes) = zstd_seekable(&data, 60_000);
+            file.extend_from_slice(&seek_table(&sizes, checksum));
+            assert_seeks(open(file, Format::Zstd), &data);
+        }
+    }
+
+    #[test]
+    fn seek_plain() {
+        let data = sample(3_500_000);
+        assert_seeks(open(data.clone(), Format::Plain), &data);
+    }
+
+    #[test]
+    fn reject_linked_lz4() {
+        let file = lz4(&sample(300_000), BlockMode::Linked, 50_000);
+        let e = SeekableDetectReader::from_reader(Cursor::new(file), Format::Lz4)
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn reject_zstd_without_seek_table() {
+        let file = zstd::bulk::compress(&sample(300_000), 3).unwrap();
+        let e = SeekableDetectReader::from_reader(Cursor::new(file), Format::Zstd)
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn reject_mismatched_seek_table() {
+        let data = sample(300_000);
+        let (frames, sizes) = zstd_seekable(&data, 60_000);
+
+        let mut short = sizes.clone();
+        short[0].0 -= 1;
+        let mut missing = sizes.clone();
+        missing.pop();
+
+        for sizes in &[short, missing] {
+            let mut file = frames.clone();
+            file.extend_from_slice(&seek_table(sizes, false));
+            let e = SeekableDetectReader::from_reader(Cursor::new(file), Format::Zstd)
+                .err()
+                .unwrap();
+            assert_eq!(e.kind(), ErrorKind::CorruptStream);
+        }
+
+        // Decompressed size is checked on decoding.
+        let mut wrong = sizes;
+        wrong[1].1 += 1;
+        let mut file = frames;
+        file.extend_from_slice(&seek_table(&wrong, false));
+        let mut r = open(file, Format::Zstd);
+        r.seek(SeekFrom::Start(60_000)).unwrap();
+        assert!(r.fill_buf().is_err());
+    }
+
+    #[test]
+    fn reject_unsupported_format() {
+        let e = SeekableDetectReader::from_reader(Cursor::new(Vec::new()), Format::Xz)
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,61 @@
+//! Standard streams for the `"-"` path.
+//!
+//! Standard streams are duplicated as `File`, so they can be passed to wrapper builders like ordinary files.
+
+use std::fs::File;
+use std::io::{self, Result, Write};
+use std::path::Path;
+
+/// Check `path` is `"-"`, the conventional name of standard input and output.
+pub(crate) fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Duplicate standard input as `File`.
+pub(crate) fn stdin() -> Result<File> {
+    imp::dup(&io::stdin())
+}
+
+/// Duplicate standard output as `File`.
+///
+/// Data already buffered in `std::io::Stdout` is flushed first, so it is not reordered after our output.
+pub(crate) fn stdout() -> Result<File> {
+    let stdout = io::stdout();
+    stdout.lock().flush()?;
+    imp::dup(&stdout)
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::fs::File;
+    use std::io::Result;
+    use std::os::unix::io::AsFd;
+
+    pub(super) fn dup<S: AsFd>(s: &S) -> Result<File> {
+        Ok(File::from(s.as_fd().try_clone_to_owned()?))
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::fs::File;
+    use std::io::Result;
+    use std::os::windows::io::AsHandle;
+
+    pub(super) fn dup<S: AsHandle>(s: &S) -> Result<File> {
+        Ok(File::from(s.as_handle().try_clone_to_owned()?))
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod imp {
+    use std::fs::File;
+    use std::io::{Error, ErrorKind, Result};
+
+    pub(super) fn dup<S>(_: &S) -> Result<File> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "standard streams are not supported on this platform",
+        ))
+    }
+}
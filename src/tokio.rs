@@ -0,0 +1,255 @@
+//! Async reader and writer for tokio.
+
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use ::tokio::fs::File;
+use ::tokio::io::{
+    AsyncBufRead, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, BufWriter, ReadBuf,
+};
+use async_compression::tokio::bufread::{
+    BzDecoder, GzipDecoder, Lz4Decoder, LzmaDecoder, XzDecoder, ZstdDecoder,
+};
+use async_compression::tokio::write::{
+    BzEncoder, GzipEncoder, Lz4Encoder, LzmaEncoder, XzEncoder, ZstdEncoder,
+};
+
+use crate::error::{mark_io, SourceReader};
+use crate::{detect_format, read_error, stdio};
+use crate::{Error, Format, Level, ReadOptions, Result, WriteOptions};
+
+/// `AsyncBufRead` counterpart of [`DetectReader`](struct.DetectReader.html) for tokio.
+///
+/// ```no_run
+/// use detect_compression::AsyncDetectReader;
+/// use tokio::io::AsyncBufReadExt;
+///
+/// # async fn run() -> std::io::Result<()> {
+/// let mut lines = AsyncDetectReader::open("data.log.gz").await?.lines();
+/// while let Some(line) = lines.next_line().await? {
+///     println!("{}", line);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct AsyncDetectReader {
+    inner: Pin<Box<dyn AsyncBufRead + Send>>,
+    format: Format,
+    path: Option<PathBuf>,
+}
+
+impl AsyncDetectReader {
+    /// Open compressed or uncompressed file.
+    ///
+    /// The path `"-"` means standard input, whose format is detected from leading bytes.
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<AsyncDetectReader> {
+        AsyncDetectReader::open_with_options(path, &ReadOptions::new()).await
+    }
+
+    /// Open compressed or uncompressed file with options.
+    ///
+    /// [`format`](struct.ReadOptions.html#method.format), [`sniff`](struct.ReadOptions.html#method.sniff)
+    /// and [`multi_member`](struct.ReadOptions.html#method.multi_member) options are used,
+    /// and [`threads`](struct.ReadOptions.html#method.threads) is ignored.
+    /// Limits and lenient truncation are not supported yet, and fail with [`ErrorKind::InvalidInput`](enum.ErrorKind.html#variant.InvalidInput).
+    pub async fn open_with_options<P: AsRef<Path>>(
+        path: P,
+        options: &ReadOptions,
+    ) -> Result<AsyncDetectReader> {
+        let path = path.as_ref();
+        options.check_async().map_err(|e| e.with_path(path))?;
+
+        let is_stdin = stdio::is_stdio(path);
+        let f = if is_stdin {
+            stdio::stdin().map(File::from_std)
+        } else {
+            File::open(path).await
+        };
+        let f = f.map_err(|e| Error::from(e).with_path(path))?;
+
+        let res = match options.format {
+            Some(format) => new_reader(options, f, format),
+            None if is_stdin => sniff_with_fallback(options, f, Format::Plain).await,
+            None if options.sniff => sniff_with_fallback(options, f, Format::from_path(path)).await,
+            None => new_reader(options, f, Format::from_path(path)),
+        };
+        let mut r = res.map_err(|e| e.with_path(path))?;
+        r.path = Some(path.to_path_buf());
+        Ok(r)
+    }
+
+    /// Read compressed or uncompressed stream in the specified format.
+    pub fn from_reader<R>(reader: R, format: Format) -> Result<AsyncDetectReader>
+    where
+        R: 'static + AsyncRead + Send + Unpin,
+    {
+        new_reader(&ReadOptions::new(), reader, format)
+    }
+
+    /// Read compressed or uncompressed stream, detecting format from its leading bytes.
+    ///
+    /// If the leading bytes don't identify a format, the stream is read as uncompressed.
+    pub async fn sniff_reader<R>(reader: R) -> Result<AsyncDetectReader>
+    where
+        R: 'static + AsyncRead + Send + Unpin,
+    {
+        sniff_with_fallback(&ReadOptions::new(), reader, Format::Plain).await
+    }
+
+    /// Format of the stream.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+}
+
+impl AsyncRead for AsyncDetectReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let path = this.path.as_deref();
+        let format = this.format;
+        this.inner
+            .as_mut()
+            .poll_read(cx, buf)
+            .map_err(|e| read_error(e, path, format))
+    }
+}
+
+impl AsyncBufRead for AsyncDetectReader {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+        let path = this.path.as_deref();
+        let format = this.format;
+        this.inner
+            .as_mut()
+            .poll_fill_buf(cx)
+            .map_err(|e| read_error(e, path, format))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.get_mut().inner.as_mut().consume(amt)
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for SourceReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf).map_err(mark_io)
+    }
+}
+
+/// `AsyncWrite` counterpart of [`DetectWriter`](struct.DetectWriter.html) for tokio.
+///
+/// Async drop is not possible, so [`finalize()`](#method.finalize) must be awaited to complete the output.
+/// Dropping the writer without finalize leaves incomplete output.
+///
+/// ```no_run
+/// use detect_compression::{AsyncDetectWriter, Level};
+/// use tokio::io::AsyncWriteExt;
+///
+/// # async fn run() -> std::io::Result<()> {
+/// let mut writer = AsyncDetectWriter::create("data.log.zst", Level::Default).await?;
+/// writer.write_all(b"Hello, world!\n").await?;
+/// writer.finalize().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct AsyncDetectWriter {
+    inner: Pin<Box<dyn AsyncWrite + Send>>,
+    format: Format,
+    path: Option<PathBuf>,
+}
+
+impl AsyncDetectWriter {
+    /// Create compressed or uncompressed file.
+    ///
+    /// The path `"-"` means standard output, which is written uncompressed.
+    pub async fn create<P: AsRef<Path>>(path: P, level: Level) -> Result<AsyncDetectWriter> {
+        AsyncDetectWriter::create_with_options(path, level, &WriteOptions::new()).await
+    }
+
+    /// Create compressed or uncompressed file with options.
+    ///
+    /// [`format`](struct.WriteOptions.html#method.format) and [`level_overrides`](struct.WriteOptions.html#method.level_overrides) options are used.
+    /// Atomic creation, format-specific header options, multithreading and drop policy are not supported yet,
+    /// and fail with [`ErrorKind::InvalidInput`](enum.ErrorKind.html#variant.InvalidInput).
+    pub async fn create_with_options<P: AsRef<Path>>(
+        path: P,
+        level: Level,
+        options: &WriteOptions,
+    ) -> Result<AsyncDetectWriter> {
+        let path = path.as_ref();
+        let format = options.format.unwrap_or_else(|| Format::from_path(path));
+
+        options
+            .check_async()
+            .map_err(|e| e.with_context(Some(path), Some(format)))?;
+
+        let f = if stdio::is_stdio(path) {
+            stdio::stdout().map(File::from_std)
+        } else {
+            File::create(path).await
+        };
+        let f = f.map_err(|e| Error::from(e).with_context(Some(path), Some(format)))?;
+
+        let mut w = new_writer(options, f, format, level).map_err(|e| e.with_path(path))?;
+        w.path = Some(path.to_path_buf());
+        Ok(w)
+    }
+
+    /// Write to `writer` in the specified format.
+    pub fn from_writer<W>(writer: W, format: Format, level: Level) -> Result<AsyncDetectWriter>
+    where
+        W: 'static + AsyncWrite + Send + Unpin,
+    {
+        new_writer(&WriteOptions::new(), writer, format, level)
+    }
+
+    /// Format of the output.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Write the end of the compressed stream and shut down the underlying writer.
+    pub async fn finalize(mut self) -> Result<()> {
+        self.inner.shutdown().await.map_err(|e| self.write_error(e))
+    }
+
+    fn write_error(&self, e: io::Error) -> Error {
+        Error::from(e).with_context(self.path.as_deref(), Some(self.format))
+    }
+}
+
+impl AsyncWrite for AsyncDetectWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let res = this.inner.as_mut().poll_write(cx, buf);
+        res.map_err(|e| this.write_error(e).into())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let res = this.inner.as_mut().poll_flush(cx);
+        res.map_err(|e| this.write_error(e).into())
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let res = this.inner.as_mut().poll_shutdown(cx);
+        res.map_err(|e| this.write_error(e).into())
+    }
+}
+
+async_constructors!(AsyncDetectReader, AsyncDetectWriter);
@@ -0,0 +1,233 @@
+//! Integrity test of compressed files.
+
+use std::cell::RefCell;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::rc::Rc;
+
+use flate2::bufread::GzDecoder;
+
+use crate::stdio;
+use crate::{read_header, Error, ErrorKind, Format, ReadOptions, ReadWrapperBuilder, Result};
+
+/// Length of leading bytes to find checksum flags.
+const HEAD_LEN: usize = 8;
+
+/// Decode whole file and check its checksums, like `gzip -t`.
+///
+/// Corrupt or truncated files fail with [`ErrorKind::CorruptStream`](enum.ErrorKind.html#variant.CorruptStream)
+/// or [`ErrorKind::Truncated`](enum.ErrorKind.html#variant.Truncated).
+/// All concatenated members are decoded, and data left after the compressed stream is corruption.
+/// Decompressed data is discarded.
+///
+/// ```no_run
+/// let report = detect_compression::verify("archive.tar.zst")?;
+/// println!("{} bytes of {}", report.uncompressed, report.format);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn verify<P: AsRef<Path>>(path: P) -> Result<Report> {
+    ReadOptions::new().verify(path)
+}
+
+/// Result of [`verify()`](fn.verify.html).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    /// Format of the file.
+    pub format: Format,
+    /// Size of the file.
+    pub compressed: u64,
+    /// Size of decompressed data.
+    pub uncompressed: u64,
+    /// Whether checksums were checked.
+    pub checksum: ChecksumStatus,
+}
+
+/// Status of checksums in [`Report`](struct.Report.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChecksumStatus {
+    /// Checksums of the data matched.
+    ///
+    /// For gzip and bzip2, checksums are always present.
+    /// For LZ4, Zstandard and XZ, this is based on flags of the first frame or stream.
+    Verified,
+    /// The file has no checksum, so corruption may be undetected.
+    ///
+    /// Legacy LZMA and uncompressed files have no checksum.
+    Absent,
+}
+
+pub(crate) fn run(options: &ReadOptions, path: &Path) -> Result<Report> {
+    let mut options = options.clone();
+    options.allow_truncated(false).multi_member(true);
+
+    let head = Rc::new(RefCell::new(Vec::with_capacity(HEAD_LEN)));
+    let mut r = options.open_with_wrapper(path, HeadBuilder(head.clone()))?;
+    let format = r.format();
+    let uncompressed = match io::copy(&mut r, &mut io::sink()) {
+        Ok(n) => n,
+        Err(e) => {
+            let e = Error::from(e);
+            // Multi-member gzip decoder takes data after the last member as a truncated header.
+            let gzip = format == Format::Gzip || format == Format::Bgzf;
+            if e.kind() == ErrorKind::Truncated && gzip && !stdio::is_stdio(path) {
+                let f = File::open(path).map(BufReader::new);
+                if f.and_then(has_trailing_garbage).unwrap_or(false) {
+                    return Err(trailing_data(path, format));
+                }
+            }
+            return Err(e);
+        }
+    };
+
+    let compressed = r.limit.bytes_in();
+    // Decoders stopping at end of their stream may leave the rest of the file unread.
+    let len = if stdio::is_stdio(path) {
+        compressed
+    } else {
+        fs::metadata(path)
+            .map_err(|e| Error::from(e).with_path(path))?
+            .len()
+    };
+    if compressed < len {
+        return Err(trailing_data(path, format));
+    }
+
+    let head = head.borrow();
+    Ok(Report {
+        format,
+        compressed,
+        uncompressed,
+        checksum: checksum_status(format, &head),
+    })
+}
+
+fn trailing_data(path: &Path, format: Format) -> Error {
+    let e = io::Error::new(
+        io::ErrorKind::InvalidData,
+        "data after end of compressed stream",
+    );
+    Error::new(ErrorKind::CorruptStream)
+        .with_source(e)
+        .with_context(Some(path), Some(format))
+}
+
+/// Whether gzip members end with data that is not a gzip header, like `gzip -t` reports as trailing garbage.
+///
+/// Data starting with the gzip magic is a truncated member.
+fn has_trailing_garbage<R: BufRead + Seek>(mut r: R) -> io::Result<bool> {
+    let magic = Format::Gzip.magic().expect("gzip has magic bytes");
+    let mut members = 0;
+    loop {
+        let start = r.stream_position()?;
+        if r.fill_buf()?.is_empty() {
+            return Ok(false);
+        }
+
+        // `bufread::GzDecoder` reads no further than the end of the member.
+        match io::copy(&mut GzDecoder::new(&mut r), &mut io::sink()) {
+            Ok(_) => members += 1,
+            Err(ref e) if members > 0 && e.kind() == io::ErrorKind::UnexpectedEof => {
+                r.seek(SeekFrom::Start(start))?;
+                let mut head = [0u8; 2];
+                let n = read_header(&mut r, &mut head)?;
+                return Ok(!magic.starts_with(&head[..n]));
+            }
+            Err(_) => return Ok(false),
+        }
+    }
+}
+
+/// Find checksum flags from the header.
+fn checksum_status(format: Format, head: &[u8]) -> ChecksumStatus {
+    let flag = |pos: usize, mask: u8| head.get(pos).is_some_and(|b| b & mask != 0);
+
+    let verified = match format {
+        Format::Gzip | Format::Bgzf | Format::Bzip2 => true,
+        // FLG byte has block checksum and content checksum flags.
+        Format::Lz4 => flag(4, 0x10 | 0x04),
+        // Frame header descriptor has content checksum flag.
+        Format::Zstd => flag(4, 0x04),
+        // Stream flags have check type.
+        Format::Xz => flag(7, 0x0f),
+        Format::Lzma | Format::Plain => false,
+    };
+
+    if verified {
+        ChecksumStatus::Verified
+    } else {
+        ChecksumStatus::Absent
+    }
+}
+
+/// Keeps leading bytes of the file.
+struct HeadBuilder(Rc<RefCell<Vec<u8>>>);
+
+impl ReadWrapperBuilder for HeadBuilder {
+    type Wrapper = HeadReader;
+    fn new_wrapped_reader(self, f: File) -> HeadReader {
+        HeadReader {
+            inner: f,
+            head: self.0,
+        }
+    }
+}
+
+struct HeadReader {
+    inner: File,
+    head: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Read for HeadReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+
+        let mut head = self.head.borrow_mut();
+        let len = n.min(HEAD_LEN - head.len());
+        head.extend_from_slice(&buf[..len]);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    use super::has_trailing_garbage;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut e = GzEncoder::new(Vec::new(), Compression::default());
+        e.write_all(data).unwrap();
+        e.finish().unwrap()
+    }
+
+    fn with_tail(tail: &[u8]) -> Cursor<Vec<u8>> {
+        let mut file = gzip(b"first member\n");
+        file.extend_from_slice(&gzip(b"second member\n"));
+        file.extend_from_slice(tail);
+        Cursor::new(file)
+    }
+
+    #[test]
+    fn trailing_garbage() {
+        assert!(has_trailing_garbage(with_tail(b"garbage")).unwrap());
+        assert!(has_trailing_garbage(with_tail(b"x")).unwrap());
+    }
+
+    #[test]
+    fn truncated_member_is_not_garbage() {
+        let member = gzip(b"third member\n");
+        assert!(!has_trailing_garbage(with_tail(&member[..1])).unwrap());
+        assert!(!has_trailing_garbage(with_tail(&member[..5])).unwrap());
+        assert!(!has_trailing_garbage(with_tail(&member[..member.len() - 1])).unwrap());
+        assert!(!has_trailing_garbage(with_tail(&[])).unwrap());
+    }
+
+    #[test]
+    fn garbage_without_members() {
+        assert!(!has_trailing_garbage(Cursor::new(b"garbage".to_vec())).unwrap());
+    }
+}